@@ -1,6 +1,14 @@
+#[cfg(unix)]
+mod terminal;
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-  tauri::Builder::default()
+  let builder = tauri::Builder::default();
+  #[cfg(unix)]
+  let builder = builder
+    .manage(terminal::Terminal::default())
+    .invoke_handler(tauri::generate_handler![terminal::pty_spawn, terminal::pty_write, terminal::pty_resize]);
+  builder
     .setup(|app| {
       if cfg!(debug_assertions) {
         app.handle().plugin(
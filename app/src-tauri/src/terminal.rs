@@ -0,0 +1,88 @@
+use std::process::Command;
+use std::sync::Mutex;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, State};
+use terminal_core::{PtySession, PtySize, PtyWriter};
+use tokio::sync::oneshot;
+
+/// A shell started by [`pty_spawn`]: its writer, and how to stop the task
+/// that owns it.
+struct Running {
+  writer: PtyWriter,
+  stop: oneshot::Sender<()>,
+  task: JoinHandle<()>,
+}
+
+impl Running {
+  /// Kill the shell and wait until its output task has finished.
+  async fn stop(self) {
+    let _ = self.stop.send(());
+    let _ = self.task.await;
+  }
+}
+
+/// The shell running behind the terminal view, if any.
+#[derive(Default)]
+pub struct Terminal(Mutex<Option<Running>>);
+
+impl Terminal {
+  fn writer(&self) -> Result<PtyWriter, String> {
+    let running = self.0.lock().unwrap();
+    running.as_ref().map(|r| r.writer.clone()).ok_or_else(|| "no terminal is running".to_string())
+  }
+}
+
+/// Start `shell` on a PTY of the view's size, killing any shell started
+/// before. Output arrives as `pty-output` events carrying raw bytes, then a
+/// `pty-exit` event with the exit code unless the shell was replaced.
+#[tauri::command]
+pub async fn pty_spawn(
+  app: AppHandle,
+  terminal: State<'_, Terminal>,
+  shell: String,
+  rows: u16,
+  cols: u16,
+) -> Result<(), String> {
+  let previous = terminal.0.lock().unwrap().take();
+  if let Some(previous) = previous {
+    previous.stop().await;
+  }
+  let mut session =
+    PtySession::spawn_with_size(Command::new(shell), PtySize { rows, cols }).map_err(|e| format!("{e:#}"))?;
+  let writer = session.writer();
+  let (stop, mut stopped) = oneshot::channel();
+  let task = tauri::async_runtime::spawn(async move {
+    let mut buf = [0u8; 4096];
+    loop {
+      let read = tokio::select! {
+        read = session.read(&mut buf) => read,
+        _ = &mut stopped => {
+          let _ = session.kill().await;
+          return;
+        }
+      };
+      match read {
+        Ok(0) | Err(_) => break,
+        Ok(n) => {
+          let _ = app.emit("pty-output", buf[..n].to_vec());
+        }
+      }
+    }
+    let code = session.wait().await.unwrap_or(1);
+    let _ = app.emit("pty-exit", code);
+  });
+  *terminal.0.lock().unwrap() = Some(Running { writer, stop, task });
+  Ok(())
+}
+
+/// Send keystrokes to the running shell.
+#[tauri::command]
+pub async fn pty_write(terminal: State<'_, Terminal>, data: String) -> Result<(), String> {
+  terminal.writer()?.write_all(data.as_bytes()).await.map_err(|e| e.to_string())
+}
+
+/// Follow the terminal view's size; the shell gets `SIGWINCH`.
+#[tauri::command]
+pub fn pty_resize(terminal: State<'_, Terminal>, rows: u16, cols: u16) -> Result<(), String> {
+  terminal.writer()?.resize(PtySize { rows, cols }).map_err(|e| e.to_string())
+}
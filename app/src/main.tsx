@@ -1,10 +1,63 @@
-import React, { useState, useEffect } from 'react';
+import React, { useState, useEffect, useRef } from 'react';
 import ReactDOM from 'react-dom/client';
 import { motion } from 'framer-motion';
 import logoLight from '../mustache-light.png';
 import logoDark from '../mustache-dark.png';
+import { appendOutput, followResize, keyInput, spawnPty, writePty } from './terminal';
 import './index.css';
 
+/** Pixel size of one character cell in the terminal view's font. */
+const CELL_WIDTH = 8;
+const CELL_HEIGHT = 16;
+
+/** The user's shell on a PTY, sized to and following the view. */
+function TerminalView() {
+  const ref = useRef<HTMLPreElement>(null);
+  const [screen, setScreen] = useState('');
+  useEffect(() => {
+    const el = ref.current;
+    if (!el) return;
+    const decoder = new TextDecoder();
+    let cancelled = false;
+    let stop = () => {};
+    spawnPty('sh', el, CELL_WIDTH, CELL_HEIGHT, (bytes) =>
+      setScreen((s) => appendOutput(s, decoder.decode(bytes, { stream: true }))),
+    )
+      .then((unlisten) => {
+        const unfollow = followResize(el, CELL_WIDTH, CELL_HEIGHT);
+        stop = () => {
+          unlisten();
+          unfollow();
+        };
+        if (cancelled) stop();
+      })
+      .catch((e) => setScreen(String(e)));
+    return () => {
+      cancelled = true;
+      stop();
+    };
+  }, []);
+  const onKeyDown = (e: React.KeyboardEvent) => {
+    // Leave the command palette shortcut to the app.
+    if ((e.metaKey || e.ctrlKey) && e.key === 'k') return;
+    const input = keyInput(e.nativeEvent);
+    if (input !== null) {
+      e.preventDefault();
+      void writePty(input);
+    }
+  };
+  return (
+    <pre
+      ref={ref}
+      tabIndex={0}
+      onKeyDown={onKeyDown}
+      className="flex-1 w-full overflow-auto p-2 font-mono text-[13px] leading-4 outline-none whitespace-pre-wrap"
+    >
+      {screen}
+    </pre>
+  );
+}
+
 function App() {
   const [theme, setTheme] = useState<'light' | 'dark'>('dark');
   const [palette, setPalette] = useState(false);
@@ -31,6 +84,7 @@ function App() {
         onClick={() => setTheme(theme === 'light' ? 'dark' : 'light')}
       />
       <div className="p-2">CLAppy</div>
+      <TerminalView />
       {palette && (
         <div className="fixed inset-0 bg-black/50 flex items-start justify-center pt-20" onClick={() => setPalette(false)}>
           <div className="bg-white dark:bg-gray-800 rounded-xl shadow-lg p-4 w-80" onClick={(e) => e.stopPropagation()}>
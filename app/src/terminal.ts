@@ -0,0 +1,94 @@
+import { invoke } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
+
+/** A terminal window size in character cells. */
+export interface PtySize {
+  rows: number;
+  cols: number;
+}
+
+/** How many cells of `cellWidth` x `cellHeight` pixels fit in `el`. */
+export function fitSize(el: HTMLElement, cellWidth: number, cellHeight: number): PtySize {
+  return {
+    rows: Math.max(1, Math.floor(el.clientHeight / cellHeight)),
+    cols: Math.max(1, Math.floor(el.clientWidth / cellWidth)),
+  };
+}
+
+/** Start `shell` on a PTY sized to fit `el`, passing its output to `onOutput`. */
+export async function spawnPty(
+  shell: string,
+  el: HTMLElement,
+  cellWidth: number,
+  cellHeight: number,
+  onOutput: (bytes: Uint8Array) => void,
+): Promise<UnlistenFn> {
+  const unlisten = await listen<number[]>('pty-output', (e) => onOutput(new Uint8Array(e.payload)));
+  try {
+    await invoke('pty_spawn', { shell, ...fitSize(el, cellWidth, cellHeight) });
+  } catch (e) {
+    unlisten();
+    throw e;
+  }
+  return unlisten;
+}
+
+/** Send keystrokes to the running shell. */
+export function writePty(data: string): Promise<void> {
+  return invoke('pty_write', { data });
+}
+
+/** Resize the PTY whenever `el` changes size; returns a function that stops watching. */
+export function followResize(el: HTMLElement, cellWidth: number, cellHeight: number): () => void {
+  let last = '';
+  const observer = new ResizeObserver(() => {
+    const size = fitSize(el, cellWidth, cellHeight);
+    const key = `${size.rows}x${size.cols}`;
+    if (key !== last) {
+      last = key;
+      void invoke('pty_resize', { ...size });
+    }
+  });
+  observer.observe(el);
+  return () => observer.disconnect();
+}
+
+const KEYS: Record<string, string> = {
+  Enter: '\r',
+  Backspace: '\x7f',
+  Tab: '\t',
+  Escape: '\x1b',
+  ArrowUp: '\x1b[A',
+  ArrowDown: '\x1b[B',
+  ArrowRight: '\x1b[C',
+  ArrowLeft: '\x1b[D',
+};
+
+/** The bytes a terminal sends for `e`, or `null` for keys it ignores. */
+export function keyInput(e: KeyboardEvent): string | null {
+  if (e.ctrlKey && e.key.length === 1 && /[a-z]/i.test(e.key)) {
+    return String.fromCharCode(e.key.toUpperCase().charCodeAt(0) - 64);
+  }
+  if (e.metaKey) return null;
+  return KEYS[e.key] ?? (e.key.length === 1 ? e.key : null);
+}
+
+/** `screen` with `chunk` of output applied, escape sequences dropped. */
+export function appendOutput(screen: string, chunk: string): string {
+  // eslint-disable-next-line no-control-regex
+  const text = chunk.replace(/\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07]*\x07|.)/g, '');
+  let out = screen;
+  for (const ch of text) {
+    // A carriage return stays pending: before a newline it changes nothing,
+    // before anything else the line is redrawn from its start.
+    if (out.endsWith('\r')) {
+      out = ch === '\n' || ch === '\r' ? out.slice(0, -1) : out.slice(0, out.lastIndexOf('\n') + 1);
+    }
+    if (ch === '\b') {
+      out = out.slice(0, -1);
+    } else if (ch !== '\x07') {
+      out += ch;
+    }
+  }
+  return out;
+}
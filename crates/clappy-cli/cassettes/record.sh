@@ -0,0 +1,51 @@
+#!/bin/sh
+# Re-record the router test cassettes: each session runs the clappy CLI with
+# --record against mock-llm serving rules/<name>.toml, in a fresh home and
+# project directory. Build both binaries first:
+#   cargo build -p clappy-cli -p mock-llm
+set -eu
+
+here=$(cd "$(dirname "$0")" && pwd)
+bin=${CARGO_TARGET_DIR:-$here/../../../target}/debug
+session=${TMPDIR:-/tmp}/clappy-session
+port=18434
+
+# session <cassette> <rules> [VAR=value ...] -- <line>...
+session() {
+    cassette=$1 rules=$2
+    shift 2
+    vars=""
+    while [ "$1" != "--" ]; do vars="$vars $1"; shift; done
+    shift
+    rm -rf "$session" && mkdir -p "$session/home/project"
+    "$bin/mock-llm" --rules "$here/rules/$rules.toml" --addr "127.0.0.1:$port" >/dev/null &
+    mock=$!
+    sleep 1
+    printf '%s\n' "$@" | (cd "$session/home/project" && env HOME="$session/home" \
+        CLAPPY_LLM_PROVIDER=ollama CLAPPY_LLM_BASE_URL="http://127.0.0.1:$port" CLAPPY_LLM_MODEL=m $vars \
+        "$bin/clappy-cli" --record "$cassette") || true
+    kill "$mock"
+    wait "$mock" 2>/dev/null || true
+}
+
+for name in router chatty classify models profile redacted structured; do
+    rm -f "$here/$name.json"
+done
+rm -f "$here/../../clappy-e2e/cassettes/translate.json"
+
+for line in "list files" "list files, folders and symlinks" "open the website of my bank" "/explainer" \
+    "list tmp" "hello" "create a venv, then run tests" "/plan build and test"; do
+    session "$here/router.json" router -- "$line"
+done
+session "$here/../../clappy-e2e/cassettes/translate.json" router -- "list files"
+for line in "list files" "do the thing"; do
+    session "$here/chatty.json" chatty -- "$line"
+done
+session "$here/classify.json" router -- "i'd rather talk to phi3 now"
+session "$here/classify.json" classify CLAPPY_UI_LLM_CLASSIFY=true -- "i'd rather talk to phi3 now"
+session "$here/models.json" models -- "/model"
+session "$here/profile.json" profile -- "list files"
+session "$here/redacted.json" redacted -- "show my aws key id" "mail ops@corp.example the report"
+for line in "summarize my uptime" "show disk usage" "wipe the scratch disk" "list files"; do
+    session "$here/structured.json" structured CLAPPY_UI_STRUCTURED=true -- "$line"
+done
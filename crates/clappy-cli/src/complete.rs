@@ -0,0 +1,81 @@
+#![deny(clippy::all)]
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::sync::{Arc, Mutex};
+
+/// Slash commands offered while the first word is typed.
+const COMMANDS: &[&str] = &["/ai off", "/ai on", "/explain", "/model", "/plan", "/profile", "/usage"];
+
+/// Tab-completion candidates, shared between the router and the line editor.
+#[derive(Debug, Clone, Default)]
+pub struct Completions {
+    models: Arc<Mutex<Vec<String>>>,
+    profiles: Arc<Mutex<Vec<String>>>,
+}
+
+impl Completions {
+    pub fn set_models(&self, models: Vec<String>) {
+        *self.models.lock().unwrap() = models;
+    }
+
+    pub fn set_profiles(&self, profiles: Vec<String>) {
+        *self.profiles.lock().unwrap() = profiles;
+    }
+
+    /// Where the word before the cursor starts, and the candidates to replace it with.
+    pub fn candidates(&self, line: &str) -> (usize, Vec<String>) {
+        let (start, pool) = if line.starts_with("/model ") {
+            ("/model ".len(), self.models.lock().unwrap().clone())
+        } else if line.starts_with("/profile ") {
+            ("/profile ".len(), self.profiles.lock().unwrap().clone())
+        } else if line.starts_with('/') {
+            (0, COMMANDS.iter().map(|c| c.to_string()).collect())
+        } else {
+            return (0, Vec::new());
+        };
+        let word = &line[start..];
+        (start, pool.into_iter().filter(|c| c.starts_with(word)).collect())
+    }
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("/mo", 0, &["/model"])]
+    #[case("/ai", 0, &["/ai off", "/ai on"])]
+    #[case("/model ll", 7, &["llama3:latest", "llava:7b"])]
+    #[case("/model phi", 7, &[])]
+    #[case("/profile w", 9, &["work"])]
+    #[case("list files", 0, &[])]
+    fn completes(#[case] line: &str, #[case] start: usize, #[case] expected: &[&str]) {
+        let completions = Completions::default();
+        completions.set_models(vec!["llama3:latest".into(), "llava:7b".into(), "mistral".into()]);
+        completions.set_profiles(vec!["local".into(), "work".into()]);
+        assert_eq!(completions.candidates(line), (start, expected.iter().map(|s| s.to_string()).collect()));
+    }
+}
@@ -33,6 +33,11 @@ impl ContextEngine {
             .join("\n")
     }
 
+    /// The most recent output block, skipping `exit: N` markers.
+    pub fn last_output(&self) -> Option<&Block> {
+        self.history.iter().rev().find(|b| !b.text.starts_with("exit: "))
+    }
+
     pub fn cached_cmd(&self, input: &str) -> Option<String> {
         self.db.get(input).ok().flatten().map(|v| String::from_utf8_lossy(&v).to_string())
     }
@@ -60,6 +65,16 @@ mod tests {
         assert!(!ctx.context().contains("b0"));
     }
 
+    #[rstest]
+    fn last_output_skips_exit() {
+        let dir = tempdir().unwrap();
+        let mut ctx = ContextEngine::new(dir.path().to_str().unwrap());
+        assert!(ctx.last_output().is_none());
+        ctx.push(Block { text: "$ ls\nREADME.md".into() });
+        ctx.push(Block { text: "exit: 0".into() });
+        assert_eq!(ctx.last_output().map(|b| b.text.as_str()), Some("$ ls\nREADME.md"));
+    }
+
     #[rstest]
     fn cache_roundtrip() {
         let dir = tempdir().unwrap();
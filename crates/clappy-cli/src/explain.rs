@@ -0,0 +1,93 @@
+#![deny(clippy::all)]
+
+use anyhow::Result;
+use colored::Colorize;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use llm_client::{Intent, LlmProvider, build_prompt};
+
+/// What `/explain` should explain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExplainTarget {
+    /// A command line, explained flag by flag.
+    Command(String),
+    /// A captured output block.
+    Output(String),
+}
+
+/// Ask the model to explain `target`. Nothing is executed.
+pub async fn explain<P: LlmProvider + ?Sized>(provider: &P, target: &ExplainTarget) -> Result<String> {
+    let prompt = match target {
+        ExplainTarget::Command(cmd) => build_prompt(Intent::Explain, cmd, None, None),
+        ExplainTarget::Output(text) => {
+            let mut prompt = build_prompt(Intent::Explain, "", Some(text), None);
+            prompt.user = "Explain the recent terminal output: what happened and anything that needs attention.".into();
+            prompt
+        }
+    };
+    Ok(provider.complete(prompt).await?.text.trim().to_string())
+}
+
+static CODE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`\n]+)`").unwrap());
+static FLAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\s*(?:[-*]|\d+\.)?\s*)(--?[A-Za-z0-9][\w-]*)").unwrap());
+
+/// Highlight an explanation for the terminal: flags, inline code and code blocks.
+pub fn render(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            out.push(format!("  {}", line.cyan()));
+            continue;
+        }
+        if let Some(heading) = line.strip_prefix('#') {
+            out.push(heading.trim_start_matches('#').trim().bold().underline().to_string());
+            continue;
+        }
+        let line = FLAG_RE.replace(line, |c: &Captures| format!("{}{}", &c[1], c[2].green().bold()));
+        let line = CODE_RE.replace_all(&line, |c: &Captures| c[1].yellow().to_string());
+        out.push(line.into_owned());
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use llm_client::{Prompt, Resp};
+    use rstest::rstest;
+
+    struct EchoProvider;
+
+    #[async_trait]
+    impl LlmProvider for EchoProvider {
+        async fn complete(&self, req: Prompt) -> Result<Resp> {
+            Ok(Resp { text: format!("{}\n{}", req.system.unwrap_or_default(), req.user), usage: None })
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn explain_command_and_output() {
+        let cmd = explain(&EchoProvider, &ExplainTarget::Command("tar -xzvf a.tgz".into())).await.unwrap();
+        assert!(cmd.contains("flag by flag"));
+        assert!(cmd.ends_with("tar -xzvf a.tgz"));
+
+        let out = explain(&EchoProvider, &ExplainTarget::Output("permission denied".into())).await.unwrap();
+        assert!(out.contains("```\npermission denied\n```"));
+        assert!(out.ends_with("anything that needs attention."));
+    }
+
+    #[rstest]
+    fn render_highlights() {
+        colored::control::set_override(false);
+        let text = "# tar\n- `-x` extract\n  -z gunzip\n```\ntar -xzf a.tgz\n```";
+        assert_eq!(render(text), "tar\n- -x extract\n  -z gunzip\n  tar -xzf a.tgz");
+    }
+}
@@ -2,21 +2,51 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use clappy_cli::{CommandRouter, ContextEngine};
-use llm_client::{LlmConfig, Provider, Prompt, provider_from_config};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use colored::Colorize;
+use clappy_cli::config::Source;
+use clappy_cli::{
+    CommandRouter, Completions, Config, ConfigLoader, ContextEngine, ExplainTarget, ProviderFactory, UsageTracker,
+    explain, guarded,
+};
+use llm_client::{
+    LlmConfig, LlmProvider, Matching, Prompt, RecordingProvider, ReplayProvider, Redactor, pin_environment,
+    provider_from_config,
+};
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Cli {
-    #[arg(long, default_value = "ollama")]
-    provider: String,
-    #[arg(long, default_value = "llama3")]
-    model: String,
+    /// Start with a `[profiles.<name>]` table instead of `[llm]`
+    #[arg(long)]
+    profile: Option<String>,
+    /// Overrides `llm.provider`, or the selected profile's, from clappy.toml
+    #[arg(long)]
+    provider: Option<String>,
+    /// Overrides `llm.model`, or the selected profile's, from clappy.toml
+    #[arg(long)]
+    model: Option<String>,
+    /// Overrides `llm.base_url`, or the selected profile's, from clappy.toml
+    #[arg(long)]
+    base_url: Option<String>,
     #[arg(long, default_value_t = false)]
     insecure_telemetry: bool,
     #[arg(long, default_value_t = false)]
     i_know: bool,
+    /// Append every model request and reply to this cassette file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Answer from a recorded cassette instead of calling the model
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Only replay recordings whose prompt matches exactly
+    #[arg(long, default_value_t = false, requires = "replay")]
+    strict_replay: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -24,31 +54,107 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     Predict { input: String },
+    /// Explain a command flag by flag without running it
+    Explain {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Inspect the layered configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the effective settings and where each one came from
+    Show,
+}
+
+/// Defaults, config files and environment, then command-line flags on top.
+fn load_config(args: &Cli) -> Result<ConfigLoader> {
+    let mut loader = ConfigLoader::discover()?;
+    let flags = [
+        ("llm.provider", &args.provider, "--provider"),
+        ("llm.model", &args.model, "--model"),
+        ("llm.base_url", &args.base_url, "--base-url"),
+        ("profile", &args.profile, "--profile"),
+    ];
+    for (key, value, flag) in flags {
+        if let Some(value) = value {
+            loader.set(key, value.as_str(), Source::Flag(flag));
+        }
+    }
+    if args.insecure_telemetry {
+        loader.set("ui.telemetry", true, Source::Flag("--insecure-telemetry"));
+    }
+    if args.i_know {
+        loader.set("ui.i_know", true, Source::Flag("--i-know"));
+    }
+    Ok(loader)
+}
+
+/// Providers for a config, recorded with `--record` or replaced with `--replay`.
+fn provider_factory(args: &Cli) -> ProviderFactory {
+    let (record, replay) = (args.record.clone(), args.replay.clone());
+    let matching = if args.strict_replay { Matching::Strict } else { Matching::Fuzzy };
+    Arc::new(move |cfg: &LlmConfig| -> Result<Box<dyn LlmProvider>> {
+        if let Some(path) = &replay {
+            let provider = ReplayProvider::open(path, matching)?;
+            // Describe the recording machine, so prompts match what was recorded.
+            if let Some(env) = provider.environment() {
+                pin_environment(env.clone())?;
+            }
+            return Ok(Box::new(provider));
+        }
+        let provider = provider_from_config(cfg);
+        match &record {
+            Some(path) => Ok(Box::new(RecordingProvider::new(provider, path)?)),
+            None => Ok(provider),
+        }
+    })
+}
+
+/// Where daily usage totals are kept.
+const USAGE_FILE: &str = "usage.json";
+
+/// A provider for a one-off command, redacted and held to the spend caps like the REPL's.
+fn one_shot(factory: &ProviderFactory, cfg: &LlmConfig, config: &Config, redactor: &Redactor) -> Result<Arc<dyn LlmProvider>> {
+    let usage = Arc::new(Mutex::new(UsageTracker::new(config.usage.clone()).persist(USAGE_FILE)));
+    Ok(guarded(cfg, factory(cfg)?, &usage, &Arc::new(Mutex::new(redactor.clone()))))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
-    let cfg = LlmConfig {
-        provider: match args.provider.as_str() {
-            "ollama" => Provider::Ollama,
-            "openrouter" => Provider::OpenRouter,
-            "aifoundry" => Provider::AIFoundry,
-            _ => Provider::Custom,
-        },
-        base_url: "http://localhost".into(),
-        api_key: None,
-        model: args.model.clone(),
-    };
+    let loader = load_config(&args)?;
+    let config = loader.build()?;
+    let cfg = config.active_llm()?;
+    let redactor = Redactor::from_config(&config.privacy)?;
+    let factory = provider_factory(&args);
 
-    if let Some(Commands::Predict { input }) = args.command {
-        let provider = provider_from_config(&cfg);
-        let resp = provider.complete(Prompt { text: input }).await?;
-        println!("{}", resp.text);
-        return Ok(());
+    match &args.command {
+        Some(Commands::Predict { input }) => {
+            let provider = one_shot(&factory, &cfg, &config, &redactor)?;
+            let resp = provider.complete(Prompt::new(input.as_str())).await?;
+            println!("{}", resp.text);
+            return Ok(());
+        }
+        Some(Commands::Explain { command }) => {
+            let provider = one_shot(&factory, &cfg, &config, &redactor)?;
+            let text = explain::explain(&*provider, &ExplainTarget::Command(command.join(" "))).await?;
+            println!("{}", explain::render(&text));
+            return Ok(());
+        }
+        Some(Commands::Config { action: ConfigAction::Show }) => {
+            print!("{}", loader.show());
+            return Ok(());
+        }
+        None => {}
     }
 
-    if args.insecure_telemetry {
+    if config.ui.telemetry {
         println!("Telemetry enabled");
         unsafe { std::env::set_var("CLAPPY_TELEMETRY", "1"); }
     } else {
@@ -56,11 +162,52 @@ async fn main() -> Result<()> {
     }
 
     let context = ContextEngine::new("context.db");
-    let mut router = CommandRouter::new(cfg, context, args.i_know);
-    let stdin = BufReader::new(tokio::io::stdin());
-    let mut lines = stdin.lines();
-    while let Some(line) = lines.next_line().await? {
-        router.handle_line(&line).await?;
+    let provider = factory(&cfg)?;
+    let mut router = CommandRouter::with_provider(cfg, provider, context);
+    router.set_provider_factory(factory);
+    router.set_profiles(config.profiles, config.profile);
+    router.set_redactor(redactor);
+    router.set_i_know(config.ui.i_know);
+    router.set_llm_classify(config.ui.llm_classify);
+    router.set_structured(config.ui.structured);
+    router.set_usage(UsageTracker::new(config.usage).persist(USAGE_FILE));
+    if std::io::stdin().is_terminal() {
+        return edit_lines(router).await;
+    }
+    let input = router.input();
+    while let Some(line) = input.next_line().await? {
+        handle(&mut router, &line).await;
     }
     Ok(())
 }
+
+async fn handle(router: &mut CommandRouter, line: &str) {
+    if let Err(e) = router.handle_line(line).await {
+        eprintln!("{}", format!("error: {e:#}").red());
+    }
+}
+
+/// Interactive loop with history and tab completion of commands, models and profiles.
+async fn edit_lines(mut router: CommandRouter) -> Result<()> {
+    // Fill the model cache up front; completion simply offers none if the server is down.
+    let _ = router.refresh_models().await;
+    let mut editor = Editor::<Completions, DefaultHistory>::new()?;
+    editor.set_helper(Some(router.completions()));
+    loop {
+        let (returned, line) = tokio::task::spawn_blocking(move || {
+            let line = editor.readline("> ");
+            (editor, line)
+        })
+        .await?;
+        editor = returned;
+        match line {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                handle(&mut router, &line).await;
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
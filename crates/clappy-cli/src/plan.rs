@@ -0,0 +1,74 @@
+#![deny(clippy::all)]
+
+use colored::Colorize;
+
+use llm_client::Plan;
+
+/// What to do with the next step of a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepChoice {
+    Run,
+    Skip,
+    Edit,
+    Quit,
+}
+
+impl StepChoice {
+    /// `r` runs the step, `s` skips it, `e` edits it and anything else,
+    /// Enter, Esc and Ctrl-C included, stops the plan.
+    pub fn from_key(key: char) -> Self {
+        match key.to_ascii_lowercase() {
+            'r' => StepChoice::Run,
+            's' => StepChoice::Skip,
+            'e' => StepChoice::Edit,
+            _ => StepChoice::Quit,
+        }
+    }
+}
+
+/// The whole plan, one numbered command per step with its rationale below.
+pub fn render(plan: &Plan) -> String {
+    let count = plan.steps.len();
+    let mut out = format!("Plan ({count} step{}):", if count == 1 { "" } else { "s" });
+    for (i, step) in plan.steps.iter().enumerate() {
+        out.push_str(&format!("\n{:>3}. {}", i + 1, format!("$ {}", step.command).cyan()));
+        if let Some(why) = &step.rationale {
+            out.push_str(&format!("\n     {}", format!("# {why}").dimmed()));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm_client::Step;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case('r', StepChoice::Run)]
+    #[case('\n', StepChoice::Quit)]
+    #[case('y', StepChoice::Quit)]
+    #[case('S', StepChoice::Skip)]
+    #[case('e', StepChoice::Edit)]
+    #[case('q', StepChoice::Quit)]
+    #[case('n', StepChoice::Quit)]
+    fn step_choice_from_key(#[case] key: char, #[case] expected: StepChoice) {
+        assert_eq!(StepChoice::from_key(key), expected);
+    }
+
+    #[rstest]
+    fn renders_steps_with_rationale() {
+        colored::control::set_override(false);
+        let plan = Plan {
+            steps: vec![
+                Step { command: "python3 -m venv .venv".into(), rationale: Some("isolate dependencies".into()) },
+                Step { command: "make test".into(), rationale: None },
+            ],
+        };
+        assert_eq!(
+            render(&plan),
+            "Plan (2 steps):\n  1. $ python3 -m venv .venv\n     # isolate dependencies\n  2. $ make test"
+        );
+    }
+}
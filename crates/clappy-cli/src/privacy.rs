@@ -0,0 +1,160 @@
+#![deny(clippy::all)]
+
+use anyhow::Result;
+use async_trait::async_trait;
+use colored::Colorize;
+use futures_util::stream::{self, StreamExt};
+use std::sync::{Arc, Mutex};
+
+use llm_client::{Findings, LlmConfig, LlmProvider, Prompt, Redactor, Resp, Role, TextStream};
+
+/// Longest placeholder held back while waiting for its closing `>`.
+const MAX_PLACEHOLDER: usize = 64;
+
+/// Redacts every prompt before handing it to the wrapped provider, and puts
+/// the redacted values back into the reply.
+pub struct Redacting {
+    inner: Box<dyn LlmProvider>,
+    redactor: Arc<Mutex<Redactor>>,
+}
+
+impl Redacting {
+    pub fn new(inner: Box<dyn LlmProvider>, redactor: Arc<Mutex<Redactor>>) -> Self {
+        Self { inner, redactor }
+    }
+
+    fn scrub(&self, req: &Prompt) -> (Prompt, Findings) {
+        self.redactor.lock().unwrap().redact_prompt(req)
+    }
+}
+
+/// Where `text` can be cut without splitting a placeholder: before a `<` that
+/// is not yet closed, otherwise at the end.
+fn complete_up_to(text: &str) -> usize {
+    match text.rfind('<') {
+        Some(i) if !text[i..].contains('>') && text.len() - i < MAX_PLACEHOLDER => i,
+        _ => text.len(),
+    }
+}
+
+#[async_trait]
+impl LlmProvider for Redacting {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let (req, found) = self.scrub(&req);
+        let mut resp = self.inner.complete(req).await?;
+        resp.text = found.restore(&resp.text);
+        Ok(resp)
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let (req, found) = self.scrub(&req);
+        let inner = self.inner.complete_stream(req).await?;
+        let usage = inner.slot();
+        let deltas = stream::unfold(Some((inner, String::new())), move |state| {
+            let found = found.clone();
+            async move {
+                let (mut inner, mut pending) = state?;
+                loop {
+                    match inner.next().await {
+                        Some(Ok(delta)) => {
+                            pending.push_str(&delta);
+                            let ready: String = pending.drain(..complete_up_to(&pending)).collect();
+                            if !ready.is_empty() {
+                                return Some((Ok(found.restore(&ready)), Some((inner, pending))));
+                            }
+                        }
+                        Some(Err(e)) => return Some((Err(e), None)),
+                        None if pending.is_empty() => return None,
+                        None => return Some((Ok(found.restore(&pending)), None)),
+                    }
+                }
+            }
+        });
+        Ok(TextStream::new(deltas).with_slot(usage))
+    }
+
+    fn answered_by(&self) -> Option<LlmConfig> {
+        self.inner.answered_by()
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.inner.supports_schema()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        self.inner.list_models().await
+    }
+}
+
+/// The messages of a redacted prompt as they go over the wire, then a summary
+/// of what was replaced.
+pub fn render_preview(prompt: &Prompt, found: &Findings) -> String {
+    let mut out = String::new();
+    for message in prompt.messages() {
+        let role = match message.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        out.push_str(&format!("{}\n{}\n\n", format!("[{role}]").bold(), message.content));
+    }
+    let summary = if found.is_empty() { "nothing redacted".to_string() } else { format!("redacted: {found}") };
+    out.push_str(&summary.cyan().to_string());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    /// Replies with what it was sent, keeping a copy of each prompt.
+    #[derive(Default)]
+    struct Echo(Arc<Mutex<Vec<String>>>);
+
+    impl Echo {
+        fn sent(&self, req: &Prompt) -> String {
+            let text = format!("{}|{}", req.system.clone().unwrap_or_default(), req.user);
+            self.0.lock().unwrap().push(text.clone());
+            text
+        }
+    }
+
+    #[async_trait]
+    impl LlmProvider for Echo {
+        async fn complete(&self, req: Prompt) -> Result<Resp> {
+            Ok(Resp { text: self.sent(&req), usage: None })
+        }
+
+        async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+            // Deltas that split placeholders, as tokens often do.
+            let text = self.sent(&req);
+            let parts: Vec<Result<String>> = text.split_inclusive(['<', 'p']).map(|s| Ok(s.to_string())).collect();
+            Ok(TextStream::new(stream::iter(parts)))
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn redacts_before_sending_and_restores_replies() {
+        let echo = Echo::default();
+        let sent = echo.0.clone();
+        let provider = Redacting::new(Box::new(echo), Arc::new(Mutex::new(Redactor::default())));
+        let prompt = Prompt::new("email bob@example.com the logs").with_system("$ cat ~/.aws\nAKIAIOSFODNN7EXAMPLE");
+        let resp = provider.complete(prompt).await.unwrap();
+        assert_eq!(sent.lock().unwrap()[0], "$ cat ~/.aws\n<aws-key>|email <email> the logs");
+        assert_eq!(resp.text, "$ cat ~/.aws\nAKIAIOSFODNN7EXAMPLE|email bob@example.com the logs");
+
+        let deltas = provider.complete_stream(Prompt::new("ping 10.0.12.7 < /dev/null")).await.unwrap();
+        let text: Vec<String> = deltas.map(|d| d.unwrap()).collect().await;
+        assert_eq!(sent.lock().unwrap()[1], "|ping <ip> < /dev/null");
+        assert_eq!(text.concat(), "|ping 10.0.12.7 < /dev/null");
+    }
+
+    #[rstest]
+    fn preview_lists_messages_and_findings() {
+        colored::control::set_override(false);
+        let (prompt, found) = Redactor::default().redact_prompt(&Prompt::new("ping 192.168.1.20").with_system("be brief"));
+        assert_eq!(render_preview(&prompt, &found), "[system]\nbe brief\n\n[user]\nping <ip>\n\nredacted: 1 ip");
+    }
+}
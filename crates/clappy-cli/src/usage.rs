@@ -0,0 +1,340 @@
+#![deny(clippy::all)]
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_stream::StreamExt;
+
+use llm_client::{LlmConfig, LlmProvider, Prompt, Resp, TextStream, Usage, UsageSlot};
+
+/// USD per million tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl Price {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt + usage.completion_tokens as f64 * self.completion) / 1_000_000.0
+    }
+}
+
+/// The `[usage]` table of `clappy.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// Prices by model name; unlisted models are treated as free.
+    pub prices: HashMap<String, Price>,
+    /// Refuse new requests once the session has spent this much (USD).
+    pub session_limit: Option<f64>,
+    /// Refuse new requests once today's spend reaches this (USD).
+    pub daily_limit: Option<f64>,
+}
+
+/// Token and spend totals over some period.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Totals {
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    /// Requests whose usage was estimated locally.
+    pub estimated: u32,
+}
+
+impl Totals {
+    fn add(&mut self, usage: &Usage, cost: f64) {
+        self.requests += 1;
+        self.prompt_tokens += u64::from(usage.prompt_tokens);
+        self.completion_tokens += u64::from(usage.completion_tokens);
+        self.cost += cost;
+        self.estimated += u32::from(usage.estimated);
+    }
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests, {} prompt + {} completion tokens, ${:.4}",
+            self.requests, self.prompt_tokens, self.completion_tokens, self.cost
+        )?;
+        if self.estimated > 0 {
+            write!(f, " ({} estimated)", self.estimated)?;
+        }
+        Ok(())
+    }
+}
+
+/// A spend limit from [`UsageConfig`] has been reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendCap {
+    pub period: &'static str,
+    pub limit: f64,
+    pub spent: f64,
+}
+
+impl fmt::Display for SpendCap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} spend cap of ${:.2} reached (${:.4} spent); raise it under [usage] in clappy.toml",
+            self.period, self.limit, self.spent
+        )
+    }
+}
+
+impl std::error::Error for SpendCap {}
+
+/// Today's date (UTC) as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86_400).unwrap_or(0);
+    date_from_days(days as i64)
+}
+
+/// Format days since the Unix epoch as `YYYY-MM-DD` (Howard Hinnant's `civil_from_days`).
+fn date_from_days(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Per-session and per-day token and spend totals.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    cfg: UsageConfig,
+    session: Totals,
+    days: BTreeMap<String, Totals>,
+    path: Option<PathBuf>,
+}
+
+impl UsageTracker {
+    pub fn new(cfg: UsageConfig) -> Self {
+        Self { cfg, ..Default::default() }
+    }
+
+    /// Keep daily totals in a JSON file at `path`, loading what is already there.
+    pub fn persist(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        self.days = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        self.path = Some(path);
+        self
+    }
+
+    pub fn session(&self) -> &Totals {
+        &self.session
+    }
+
+    pub fn day(&self, date: &str) -> Totals {
+        self.days.get(date).cloned().unwrap_or_default()
+    }
+
+    /// Fail with [`SpendCap`] when a limit has been reached.
+    pub fn check(&self) -> Result<(), SpendCap> {
+        if let Some(limit) = self.cfg.session_limit
+            && self.session.cost >= limit
+        {
+            return Err(SpendCap { period: "session", limit, spent: self.session.cost });
+        }
+        let spent = self.day(&today()).cost;
+        if let Some(limit) = self.cfg.daily_limit
+            && spent >= limit
+        {
+            return Err(SpendCap { period: "daily", limit, spent });
+        }
+        Ok(())
+    }
+
+    /// Add one request's usage and return its cost.
+    pub fn record(&mut self, model: &str, usage: &Usage) -> f64 {
+        let cost = self.cfg.prices.get(model).map_or(0.0, |p| p.cost(usage));
+        self.session.add(usage, cost);
+        self.days.entry(today()).or_default().add(usage, cost);
+        if let Some(path) = &self.path
+            && let Ok(json) = serde_json::to_string_pretty(&self.days)
+        {
+            let _ = std::fs::write(path, json);
+        }
+        cost
+    }
+
+    /// The `/usage` summary.
+    pub fn report(&self) -> String {
+        let limit = |l: Option<f64>| l.map_or("none".to_string(), |l| format!("${l:.2}"));
+        format!(
+            "session: {}\ntoday:   {}\nlimits:  session {}, daily {}",
+            self.session,
+            self.day(&today()),
+            limit(self.cfg.session_limit),
+            limit(self.cfg.daily_limit)
+        )
+    }
+}
+
+/// Wraps a provider to record every request's usage and enforce spend caps.
+pub struct Metered {
+    inner: Box<dyn LlmProvider>,
+    model: String,
+    tracker: Arc<Mutex<UsageTracker>>,
+}
+
+impl Metered {
+    pub fn new(inner: Box<dyn LlmProvider>, model: impl Into<String>, tracker: Arc<Mutex<UsageTracker>>) -> Self {
+        Self { inner, model: model.into(), tracker }
+    }
+}
+
+fn record(tracker: &Mutex<UsageTracker>, model: &str, usage: &Usage) {
+    tracker.lock().unwrap().record(model, usage);
+}
+
+#[async_trait]
+impl LlmProvider for Metered {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        self.tracker.lock().unwrap().check()?;
+        let mut resp = self.inner.complete(req.clone()).await?;
+        let usage = resp.usage.unwrap_or_else(|| Usage::estimate(&req, &resp.text));
+        let model = self.inner.answered_by().map_or_else(|| self.model.clone(), |c| c.model);
+        record(&self.tracker, &model, &usage);
+        resp.usage = Some(usage);
+        Ok(resp)
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        self.tracker.lock().unwrap().check()?;
+        let inner = self.inner.complete_stream(req.clone()).await?;
+        let model = self.inner.answered_by().map_or_else(|| self.model.clone(), |c| c.model);
+        let tracker = self.tracker.clone();
+        let usage = UsageSlot::default();
+        let slot = usage.clone();
+        // Record once the reply is complete, when the provider's counts are known.
+        let deltas = stream::unfold(Some((inner, String::new())), move |state| {
+            let (tracker, model, slot, req) = (tracker.clone(), model.clone(), slot.clone(), req.clone());
+            async move {
+                let (mut inner, mut text) = state?;
+                match inner.next().await {
+                    Some(Ok(delta)) => {
+                        text.push_str(&delta);
+                        Some((Ok(delta), Some((inner, text))))
+                    }
+                    Some(Err(e)) => Some((Err(e), None)),
+                    None => {
+                        let usage = inner.usage().unwrap_or_else(|| Usage::estimate(&req, &text));
+                        record(&tracker, &model, &usage);
+                        *slot.lock().unwrap() = Some(usage);
+                        None
+                    }
+                }
+            }
+        });
+        Ok(TextStream::new(deltas).with_slot(usage))
+    }
+
+    fn answered_by(&self) -> Option<LlmConfig> {
+        self.inner.answered_by()
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.inner.supports_schema()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        self.inner.list_models().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn priced() -> UsageConfig {
+        UsageConfig {
+            prices: HashMap::from([("gpt-4o-mini".to_string(), Price { prompt: 0.15, completion: 0.6 })]),
+            session_limit: Some(0.001),
+            daily_limit: None,
+        }
+    }
+
+    #[rstest]
+    #[case("1970-01-01", 0)]
+    #[case("2000-02-29", 11_016)]
+    #[case("2024-12-31", 20_088)]
+    fn civil_dates(#[case] date: &str, #[case] days: i64) {
+        assert_eq!(date_from_days(days), date);
+    }
+
+    #[rstest]
+    fn records_and_caps() {
+        let mut tracker = UsageTracker::new(priced());
+        assert_eq!(tracker.record("llama3", &Usage::new(1_000, 100)), 0.0);
+        assert!(tracker.check().is_ok());
+        let cost = tracker.record("gpt-4o-mini", &Usage::new(2_000, 1_000));
+        assert!((cost - 0.0009).abs() < 1e-12);
+        tracker.record("gpt-4o-mini", &Usage { prompt_tokens: 2_000, completion_tokens: 0, estimated: true });
+        assert_eq!(tracker.session().requests, 3);
+        assert_eq!(tracker.session().prompt_tokens, 5_000);
+        assert_eq!(tracker.day(&today()).estimated, 1);
+        let cap = tracker.check().unwrap_err();
+        assert_eq!(cap.period, "session");
+        assert!(tracker.report().starts_with("session: 3 requests, 5000 prompt + 1100 completion tokens, $0.0012 (1 estimated)"));
+    }
+
+    #[rstest]
+    fn daily_totals_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        let cfg = UsageConfig { daily_limit: Some(0.0005), ..priced() };
+        let mut tracker = UsageTracker::new(cfg.clone()).persist(&path);
+        tracker.record("gpt-4o-mini", &Usage::new(0, 1_000));
+
+        let reopened = UsageTracker::new(cfg).persist(&path);
+        assert_eq!(reopened.session().requests, 0);
+        assert_eq!(reopened.day(&today()).completion_tokens, 1_000);
+        assert_eq!(reopened.check().unwrap_err().period, "daily");
+    }
+
+    struct Fixed;
+
+    #[async_trait]
+    impl LlmProvider for Fixed {
+        async fn complete(&self, _req: Prompt) -> Result<Resp> {
+            Ok(Resp { text: "ls -la".into(), usage: Some(Usage::new(500, 1_000)) })
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn metered_records_and_blocks() {
+        let tracker = Arc::new(Mutex::new(UsageTracker::new(priced())));
+        let metered = Metered::new(Box::new(Fixed), "gpt-4o-mini", tracker.clone());
+
+        let mut deltas = metered.complete_stream(Prompt::new("list")).await.unwrap();
+        while deltas.next().await.is_some() {}
+        assert_eq!(deltas.usage(), Some(Usage::new(500, 1_000)));
+        assert_eq!(tracker.lock().unwrap().session().completion_tokens, 1_000);
+
+        metered.complete(Prompt::new("list")).await.unwrap();
+        let err = metered.complete(Prompt::new("list")).await.unwrap_err();
+        assert!(err.downcast_ref::<SpendCap>().is_some());
+        assert_eq!(tracker.lock().unwrap().session().requests, 2);
+    }
+}
@@ -1,9 +1,8 @@
 #![deny(clippy::all)]
-use anyhow::Result;
-use async_trait::async_trait;
 use cucumber::{given, then, when, World};
 use clappy_cli::{CommandRouter, Route, safety_scan, ContextEngine};
-use llm_client::{LlmConfig, LlmProvider, Prompt, Provider};
+use llm_client::{LlmConfig, Matching, Provider, ReplayProvider, pin_environment, provider_from_config};
+use std::sync::Arc;
 
 #[derive(World, Default)]
 struct MyWorld {
@@ -12,21 +11,15 @@ struct MyWorld {
     plugin_output: Option<String>,
 }
 
-#[async_trait(?Send)]
 #[given("a command router")]
 async fn a_command_router(world: &mut MyWorld) {
-    #[derive(Clone)]
-    struct FakeProvider;
-    #[async_trait]
-    impl LlmProvider for FakeProvider {
-        async fn complete(&self, req: Prompt) -> Result<llm_client::Resp> {
-            Ok(llm_client::Resp { text: format!("echo {}", req.text) })
-        }
-    }
-    let cfg = LlmConfig { provider: Provider::Ollama, base_url: "".into(), api_key: None, model: "m".into() };
+    let cassette = concat!(env!("CARGO_MANIFEST_DIR"), "/cassettes/translate.json");
+    let provider = ReplayProvider::open(cassette, Matching::Strict).unwrap();
+    pin_environment(provider.environment().unwrap().clone()).unwrap();
+    let cfg = LlmConfig { provider: Provider::Ollama, base_url: "".into(), api_key: None, model: "m".into(), ..Default::default() };
     let dir = tempfile::tempdir().unwrap();
     let ctx = ContextEngine::new(dir.path().to_str().unwrap());
-    world.router = Some(CommandRouter::with_provider(cfg, Box::new(FakeProvider), ctx));
+    world.router = Some(CommandRouter::with_provider(cfg, Box::new(provider), ctx));
 }
 
 #[when(regex = "^I translate \"(.+)\"$")]
@@ -67,10 +60,10 @@ async fn route_spawn(world: &mut MyWorld, shell: String) {
 
 #[when(regex = "^plugin bus processes \"(.+)\"$")]
 async fn plugin_bus(world: &mut MyWorld, line: String) {
-    let router = world.router.as_mut().unwrap();
-    let mut bus = clappy_cli::PluginBus::new(LlmConfig { provider: Provider::Ollama, base_url: "".into(), api_key: None, model: "m".into() });
+    let cfg = LlmConfig { provider: Provider::Ollama, base_url: "".into(), api_key: None, model: "m".into(), ..Default::default() };
+    let mut bus = clappy_cli::PluginBus::new();
     let _ = bus.load_dir("plugins");
-    world.plugin_output = bus.process_line(&line).unwrap();
+    world.plugin_output = bus.process_line(&line, Arc::from(provider_from_config(&cfg))).unwrap();
 }
 
 #[then(regex = "^plugin output should be \"(.+)\"$")]
@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::stream::{TextStream, UsageSlot};
+use crate::{Environment, LlmConfig, LlmProvider, ModelsUnsupported, Prompt, Resp, Usage, environment};
+
+/// One recorded request and the reply it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub prompt: Prompt,
+    pub text: String,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Recorded interactions, stored as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    /// Whether the recorded provider honoured JSON schemas.
+    #[serde(default)]
+    pub supports_schema: bool,
+    /// The machine the prompts describe; pin it to replay them strictly elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+    pub interactions: Vec<Interaction>,
+    /// What listing the models returned while recording, if it was asked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub models: Option<Vec<String>>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading cassette {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("parsing cassette {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Wraps a provider and appends every exchange to a cassette file.
+pub struct RecordingProvider {
+    inner: Box<dyn LlmProvider>,
+    path: PathBuf,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl RecordingProvider {
+    /// Record into `path`, keeping interactions already in the file.
+    pub fn new(inner: Box<dyn LlmProvider>, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut cassette = if path.exists() { Cassette::load(&path)? } else { Cassette::default() };
+        cassette.supports_schema = inner.supports_schema();
+        cassette.environment.get_or_insert_with(|| environment().clone());
+        Ok(Self { inner, path, cassette: Arc::new(Mutex::new(cassette)) })
+    }
+}
+
+fn append(cassette: &Mutex<Cassette>, path: &Path, interaction: Interaction) -> Result<()> {
+    let mut cassette = cassette.lock().unwrap();
+    cassette.interactions.push(interaction);
+    cassette.save(path)
+}
+
+#[async_trait]
+impl LlmProvider for RecordingProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let resp = self.inner.complete(req.clone()).await?;
+        let interaction = Interaction { prompt: req, text: resp.text.clone(), usage: resp.usage };
+        append(&self.cassette, &self.path, interaction)?;
+        Ok(resp)
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let inner = self.inner.complete_stream(req.clone()).await?;
+        let (cassette, path) = (self.cassette.clone(), self.path.clone());
+        let usage = UsageSlot::default();
+        let slot = usage.clone();
+        // Only complete replies are written; a stream that fails midway is not recorded.
+        let deltas = stream::unfold(Some((inner, String::new())), move |state| {
+            let (cassette, path, slot, req) = (cassette.clone(), path.clone(), slot.clone(), req.clone());
+            async move {
+                let (mut inner, mut text) = state?;
+                match inner.next().await {
+                    Some(Ok(delta)) => {
+                        text.push_str(&delta);
+                        Some((Ok(delta), Some((inner, text))))
+                    }
+                    Some(Err(e)) => Some((Err(e), None)),
+                    None => {
+                        *slot.lock().unwrap() = inner.usage();
+                        let interaction = Interaction { prompt: req, text, usage: inner.usage() };
+                        append(&cassette, &path, interaction).err().map(|e| (Err(e), None))
+                    }
+                }
+            }
+        });
+        Ok(TextStream::new(deltas).with_slot(usage))
+    }
+
+    fn answered_by(&self) -> Option<LlmConfig> {
+        self.inner.answered_by()
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.inner.supports_schema()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let models = self.inner.list_models().await?;
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.models = Some(models.clone());
+        cassette.save(&self.path)?;
+        Ok(models)
+    }
+}
+
+/// How a replayed prompt is matched against the cassette.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Matching {
+    /// The whole prompt must be identical.
+    Strict,
+    /// The user messages must be similar; system text (OS, recent output) may differ.
+    #[default]
+    Fuzzy,
+}
+
+/// No recorded interaction matches a prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoRecording {
+    pub user: String,
+}
+
+impl fmt::Display for NoRecording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "replay: no recorded reply for {:?}", self.user)
+    }
+}
+
+impl std::error::Error for NoRecording {}
+
+/// Lowest word overlap between user messages a fuzzy match accepts.
+const FUZZY_THRESHOLD: f64 = 0.5;
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(str::to_lowercase).collect()
+}
+
+/// Jaccard similarity of the word sets of `a` and `b`.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+/// Serves replies from a cassette instead of calling a model.
+///
+/// Identical prompts recorded several times are replayed in order; once all
+/// are used the last one repeats.
+pub struct ReplayProvider {
+    cassette: Cassette,
+    matching: Matching,
+    used: Mutex<Vec<bool>>,
+}
+
+impl ReplayProvider {
+    pub fn new(cassette: Cassette, matching: Matching) -> Self {
+        let used = Mutex::new(vec![false; cassette.interactions.len()]);
+        Self { cassette, matching, used }
+    }
+
+    pub fn open(path: impl AsRef<Path>, matching: Matching) -> Result<Self> {
+        Ok(Self::new(Cassette::load(path)?, matching))
+    }
+
+    /// The machine the recorded prompts describe, if the cassette says.
+    pub fn environment(&self) -> Option<&Environment> {
+        self.cassette.environment.as_ref()
+    }
+
+    fn score(&self, recorded: &Prompt, req: &Prompt) -> Option<(f64, f64)> {
+        match self.matching {
+            Matching::Strict => (recorded == req).then_some((1.0, 1.0)),
+            Matching::Fuzzy => {
+                let user = similarity(&recorded.user, &req.user);
+                let system = similarity(recorded.system.as_deref().unwrap_or(""), req.system.as_deref().unwrap_or(""));
+                (user >= FUZZY_THRESHOLD).then_some((user, system))
+            }
+        }
+    }
+
+    fn find(&self, req: &Prompt) -> Result<&Interaction, NoRecording> {
+        let mut used = self.used.lock().unwrap();
+        let mut best: Option<(usize, (f64, f64))> = None;
+        for (i, interaction) in self.cassette.interactions.iter().enumerate() {
+            let Some(score) = self.score(&interaction.prompt, req) else {
+                continue;
+            };
+            let better = match best {
+                None => true,
+                // On a tie prefer an unused recording, else the latest one.
+                Some((j, top)) => score > top || (score == top && used[j]),
+            };
+            if better {
+                best = Some((i, score));
+            }
+        }
+        let (i, _) = best.ok_or_else(|| NoRecording { user: req.user.clone() })?;
+        used[i] = true;
+        Ok(&self.cassette.interactions[i])
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ReplayProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let interaction = self.find(&req)?;
+        Ok(Resp { text: interaction.text.clone(), usage: interaction.usage })
+    }
+
+    /// The recorded reply a word at a time, as a model would stream it.
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let interaction = self.find(&req)?;
+        let deltas: Vec<Result<String>> = interaction.text.split_inclusive(' ').map(|w| Ok(w.to_string())).collect();
+        Ok(TextStream::new(stream::iter(deltas)).with_usage(interaction.usage))
+    }
+
+    /// Whether the provider that recorded the session honoured schemas.
+    fn supports_schema(&self) -> bool {
+        self.cassette.supports_schema
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        self.cassette.models.clone().ok_or_else(|| ModelsUnsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    struct Echo;
+
+    #[async_trait]
+    impl LlmProvider for Echo {
+        async fn complete(&self, req: Prompt) -> Result<Resp> {
+            Ok(Resp { text: format!("echo {}", req.user), usage: Some(Usage::new(10, 2)) })
+        }
+    }
+
+    fn recorded(user: &str, system: &str, text: &str) -> Interaction {
+        Interaction { prompt: Prompt::new(user).with_system(system), text: text.into(), usage: None }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        let recorder = RecordingProvider::new(Box::new(Echo), &path).unwrap();
+        recorder.complete(Prompt::new("list files").with_system("OS: linux")).await.unwrap();
+        let mut deltas = recorder.complete_stream(Prompt::new("show disk usage")).await.unwrap();
+        while deltas.next().await.is_some() {}
+        assert_eq!(deltas.usage(), Some(Usage::new(10, 2)));
+
+        let cassette = Cassette::load(&path).unwrap();
+        assert!(!cassette.supports_schema);
+        assert_eq!(cassette.environment.as_ref(), Some(environment()));
+        assert_eq!(cassette.interactions.len(), 2);
+        assert_eq!(cassette.interactions[1].text, "echo show disk usage");
+
+        let replay = ReplayProvider::open(&path, Matching::Strict).unwrap();
+        let resp = replay.complete(Prompt::new("list files").with_system("OS: linux")).await.unwrap();
+        assert_eq!(resp, Resp { text: "echo list files".into(), usage: Some(Usage::new(10, 2)) });
+        let err = replay.complete(Prompt::new("list files").with_system("OS: macos")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<NoRecording>(), Some(&NoRecording { user: "list files".into() }));
+    }
+
+    #[rstest]
+    #[case("list files", Some("ls"))]
+    #[case("List the files!", Some("ls"))]
+    #[case("list all the files here", None)]
+    #[case("show disk usage", Some("df -h"))]
+    #[case("install git", None)]
+    #[tokio::test]
+    async fn fuzzy_matching(#[case] user: &str, #[case] expected: Option<&str>) {
+        let cassette = Cassette {
+            interactions: vec![recorded("list files", "OS: linux", "ls"), recorded("show disk usage", "OS: linux", "df -h")],
+            ..Default::default()
+        };
+        let replay = ReplayProvider::new(cassette, Matching::Fuzzy);
+        let got = replay.complete(Prompt::new(user).with_system("OS: macos")).await.ok().map(|r| r.text);
+        assert_eq!(got.as_deref(), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn repeats_replay_in_order() {
+        let cassette = Cassette {
+            interactions: vec![recorded("retry", "", "first"), recorded("retry", "", "second")],
+            ..Default::default()
+        };
+        let replay = ReplayProvider::new(cassette, Matching::Strict);
+        let mut texts = Vec::new();
+        for _ in 0..3 {
+            texts.push(replay.complete(Prompt::new("retry").with_system("")).await.unwrap().text);
+        }
+        assert_eq!(texts, vec!["first", "second", "second"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn replays_streams_and_models() {
+        let cassette = Cassette {
+            interactions: vec![recorded("list tmp", "", "ls -la /tmp")],
+            models: Some(vec!["llama3:latest".into()]),
+            supports_schema: true,
+            ..Default::default()
+        };
+        let replay = ReplayProvider::new(cassette, Matching::Fuzzy);
+        let deltas: Vec<String> = replay.complete_stream(Prompt::new("list tmp")).await.unwrap().map(|d| d.unwrap()).collect().await;
+        assert_eq!(deltas, vec!["ls ", "-la ", "/tmp"]);
+        assert_eq!(replay.list_models().await.unwrap(), vec!["llama3:latest"]);
+        assert!(replay.supports_schema());
+        assert!(ReplayProvider::new(Cassette::default(), Matching::Fuzzy).list_models().await.unwrap_err().is::<ModelsUnsupported>());
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{Intent, LlmProvider, Prompt};
+
+/// An input line with its [`Intent`] and the part the intent applies to,
+/// e.g. the command to explain or the model to switch to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classified {
+    pub intent: Intent,
+    pub subject: String,
+}
+
+static EXPLAIN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(?:what\s+does|how\s+does|explain|(?:what\s+is|what's)\s+(?:the\s+command\s+)?`)\s*(?:the\s+command\s+)?(.+?)(?:\s+(?:do|mean|work))?\s*\??$")
+        .unwrap()
+});
+static MODEL_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(?:use|switch\s+to|(?P<verb>(?:change|switch)\s+(?:the\s+)?model\s+to))\s+(?:the\s+)?(?P<before>model\s+)?(?P<model>[A-Za-z0-9][\w.:/-]*)(?P<after>\s+model)?$")
+        .unwrap()
+});
+/// Model families common enough that "use <name>" means the model, not a tool.
+static KNOWN_MODEL_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(?:llama|codellama|mistral|mixtral|codestral|phi|qwen|gemma|deepseek|starcoder|granite|llava|command-r|gpt-|o\d|claude)")
+        .unwrap()
+});
+static PLUGIN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:gifs?|memes?|open\s+(?:the\s+)?(?:url|link|website|page))\b").unwrap()
+});
+static BACKTICK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`]+)`").unwrap());
+
+/// Cheap rule-based classification; `None` when no rule is confident.
+pub fn classify(line: &str) -> Option<Classified> {
+    let line = line.trim();
+    if let Some(cap) = EXPLAIN_RE.captures(line) {
+        let subject = BACKTICK_RE
+            .captures(line)
+            .map(|c| c[1].to_string())
+            .unwrap_or_else(|| cap[1].trim_matches(|c| c == '`' || c == '"' || c == '\'').to_string());
+        return Some(Classified { intent: Intent::Explain, subject });
+    }
+    // "use git" or "switch to zsh" name tools; only take them as a model
+    // when the line says "model" or the name is a known model family.
+    if let Some(cap) = MODEL_RE.captures(line)
+        && (["verb", "before", "after"].iter().any(|g| cap.name(g).is_some()) || KNOWN_MODEL_RE.is_match(&cap["model"]))
+    {
+        return Some(Classified { intent: Intent::ChangeModel, subject: cap["model"].to_string() });
+    }
+    if PLUGIN_RE.is_match(line) {
+        return Some(Classified { intent: Intent::PluginSuggest, subject: line.to_string() });
+    }
+    None
+}
+
+/// Ask the model for the intent of a line the rules could not place.
+///
+/// Falls back to [`Intent::Translate`] when the reply is not a known intent.
+pub async fn classify_llm<P: LlmProvider + ?Sized>(provider: &P, line: &str) -> Result<Classified> {
+    let prompt = Prompt::new(line).with_system(
+        "Classify the user's terminal input. Reply with exactly one word:\n\
+         translate - they want a shell command\n\
+         explain - they ask what a command or output means\n\
+         change_model - they want to use a different AI model\n\
+         plugin_suggest - they want a GIF, meme, URL or other plugin action",
+    );
+    let resp = provider.complete(prompt).await?;
+    let word = resp.text.trim().trim_matches(|c: char| !c.is_alphanumeric() && c != '_').to_lowercase();
+    let intent = match word.as_str() {
+        "explain" => Intent::Explain,
+        "change_model" => Intent::ChangeModel,
+        "plugin_suggest" => Intent::PluginSuggest,
+        _ => Intent::Translate,
+    };
+    Ok(Classified { intent, subject: line.trim().to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Resp;
+    use async_trait::async_trait;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("what does `tar -xzvf` do?", Some((Intent::Explain, "tar -xzvf")))]
+    #[case("explain git rebase -i", Some((Intent::Explain, "git rebase -i")))]
+    #[case("what does chmod 755 mean?", Some((Intent::Explain, "chmod 755")))]
+    #[case("what's `ls -lh`?", Some((Intent::Explain, "ls -lh")))]
+    #[case("what is my ip address", None)]
+    #[case("use mistral", Some((Intent::ChangeModel, "mistral")))]
+    #[case("switch to llama3:8b", Some((Intent::ChangeModel, "llama3:8b")))]
+    #[case("change model to qwen2.5-coder", Some((Intent::ChangeModel, "qwen2.5-coder")))]
+    #[case("show me a gif of cats", Some((Intent::PluginSuggest, "show me a gif of cats")))]
+    #[case("list files", None)]
+    #[case("use git to undo my last commit", None)]
+    #[case("use git", None)]
+    #[case("use zsh", None)]
+    #[case("switch to python3", None)]
+    #[case("use the model mycorp-chat", Some((Intent::ChangeModel, "mycorp-chat")))]
+    #[case("use the granite-code model", Some((Intent::ChangeModel, "granite-code")))]
+    fn rules(#[case] line: &str, #[case] expected: Option<(Intent, &str)>) {
+        let got = classify(line).map(|c| (c.intent, c.subject));
+        assert_eq!(got, expected.map(|(i, s)| (i, s.to_string())));
+    }
+
+    #[rstest]
+    #[case("explain", Intent::Explain)]
+    #[case(" Change_Model.\n", Intent::ChangeModel)]
+    #[case("I think translate", Intent::Translate)]
+    #[tokio::test]
+    async fn llm_fallback(#[case] reply: &str, #[case] intent: Intent) {
+        struct Fixed(String);
+
+        #[async_trait]
+        impl LlmProvider for Fixed {
+            async fn complete(&self, _req: Prompt) -> Result<Resp> {
+                Ok(Resp { text: self.0.clone(), usage: None })
+            }
+        }
+
+        let got = classify_llm(&Fixed(reply.into()), "  whatever  ").await.unwrap();
+        assert_eq!(got, Classified { intent, subject: "whatever".into() });
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+use std::time::Duration;
+
+use crate::openai::{Completion, response_format, sse_step};
+use crate::retry::retry_after;
+use crate::stream::{TextStream, UsageSlot, deltas, lines};
+use crate::{LlmConfig, LlmProvider, Prompt, Resp, Usage};
+
+/// `api-version` used when the config does not pin one.
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Provider for Azure AI Foundry (Azure OpenAI) deployments.
+#[derive(Debug, Clone)]
+pub struct FoundryProvider {
+    cfg: LlmConfig,
+    client: Client,
+}
+
+/// Error returned by Azure, e.g. `DeploymentNotFound` or `429` throttling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundryError {
+    pub status: StatusCode,
+    pub code: Option<String>,
+    pub message: String,
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for FoundryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "aifoundry: {code}: {} ({})", self.message, self.status),
+            None => write!(f, "aifoundry: {} ({})", self.message, self.status),
+        }
+    }
+}
+
+impl std::error::Error for FoundryError {}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    message: String,
+}
+
+impl FoundryError {
+    fn from_body(status: StatusCode, body: &str, retry_after: Option<Duration>) -> Self {
+        match serde_json::from_str::<ErrorBody>(body) {
+            Ok(ErrorBody { error }) => Self { status, code: error.code, message: error.message, retry_after },
+            Err(_) => Self { status, code: None, message: body.to_string(), retry_after },
+        }
+    }
+}
+
+impl FoundryProvider {
+    pub fn new(cfg: LlmConfig) -> Self {
+        Self { cfg, client: Client::new() }
+    }
+
+    fn url(&self) -> String {
+        let deployment = self.cfg.deployment.as_deref().unwrap_or(&self.cfg.model);
+        let version = self.cfg.api_version.as_deref().unwrap_or(DEFAULT_API_VERSION);
+        format!(
+            "{}/openai/deployments/{deployment}/chat/completions?api-version={version}",
+            self.cfg.base_url.trim_end_matches('/'),
+        )
+    }
+
+    async fn send(&self, req: &Prompt, stream: bool) -> Result<reqwest::Response> {
+        let mut body = json!({ "messages": req.messages(), "stream": stream });
+        if let Some(temperature) = self.cfg.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(schema) = &req.schema {
+            body["response_format"] = response_format(schema);
+        }
+        let mut builder = self.client.post(self.url()).json(&body);
+        if let Some(key) = self.cfg.api_key.as_deref().filter(|k| !k.is_empty()) {
+            builder = builder.header("api-key", key);
+        }
+        let resp = builder.send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = retry_after(resp.headers());
+            let body = resp.text().await?;
+            return Err(FoundryError::from_body(status, &body, retry_after).into());
+        }
+        Ok(resp)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for FoundryProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let body = self.send(&req, false).await?.text().await?;
+        let completion: Completion = serde_json::from_str(&body)?;
+        let usage = completion.usage();
+        let text = completion
+            .into_text()
+            .ok_or_else(|| anyhow!("aifoundry: response has no choices"))?;
+        let usage = usage.unwrap_or_else(|| Usage::estimate(&req, &text));
+        Ok(Resp { text, usage: Some(usage) })
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let resp = self.send(&req, true).await?;
+        let slot = UsageSlot::default();
+        let usage = slot.clone();
+        Ok(deltas(lines(resp.bytes_stream()), move |line| sse_step(line, &usage)).with_slot(slot))
+    }
+
+    fn supports_schema(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Provider;
+    use rstest::rstest;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn cfg(base_url: String, deployment: Option<&str>, api_version: Option<&str>) -> LlmConfig {
+        LlmConfig {
+            provider: Provider::AIFoundry,
+            base_url,
+            api_key: Some("azure-key".into()),
+            model: "gpt-4o".into(),
+            deployment: deployment.map(Into::into),
+            api_version: api_version.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case(Some("prod-gpt4o"), Some("2024-06-01"), "/openai/deployments/prod-gpt4o/chat/completions", "2024-06-01")]
+    #[case(None, None, "/openai/deployments/gpt-4o/chat/completions", DEFAULT_API_VERSION)]
+    #[tokio::test]
+    async fn deployment_url(
+        #[case] deployment: Option<&str>,
+        #[case] api_version: Option<&str>,
+        #[case] expected_path: &str,
+        #[case] expected_version: &str,
+    ) {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(expected_path))
+            .and(query_param("api-version", expected_version))
+            .and(header("api-key", "azure-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "ls -la" } }]
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = FoundryProvider::new(cfg(server.uri(), deployment, api_version));
+        let resp = provider.complete(Prompt::new("list files")).await.unwrap();
+        assert_eq!(resp.text, "ls -la");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn maps_azure_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": { "code": "DeploymentNotFound", "message": "The API deployment for this resource does not exist." }
+            })))
+            .mount(&server)
+            .await;
+
+        let err = FoundryProvider::new(cfg(server.uri(), Some("missing"), None))
+            .complete(Prompt::new("hi"))
+            .await
+            .unwrap_err();
+        let err = err.downcast::<FoundryError>().unwrap();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert_eq!(err.code.as_deref(), Some("DeploymentNotFound"));
+        assert_eq!(err.message, "The API deployment for this resource does not exist.");
+    }
+}
@@ -0,0 +1,398 @@
+use anyhow::{Result, anyhow, bail};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+use crate::retry::{HttpError, retry_after};
+use crate::stream::{Step, TextStream, UsageSlot, deltas, lines};
+use crate::{LlmConfig, LlmProvider, Prompt, Resp, Usage};
+
+/// Which Ollama endpoint a prompt is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OllamaApi {
+    /// `POST /api/generate` with a raw `prompt`.
+    Generate,
+    /// `POST /api/chat` with a `messages` array.
+    Chat,
+}
+
+/// Provider speaking Ollama's native HTTP protocol.
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    cfg: LlmConfig,
+    client: Client,
+    api: OllamaApi,
+    options: Map<String, Value>,
+}
+
+/// One response object; a non-streamed reply is a single chunk with `done: true`.
+#[derive(Debug, Deserialize)]
+struct Chunk {
+    #[serde(default)]
+    response: Option<String>,
+    #[serde(default)]
+    message: Option<Message>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    eval_count: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(default)]
+    content: String,
+}
+
+impl OllamaProvider {
+    pub fn new(cfg: LlmConfig) -> Self {
+        let mut options = Map::new();
+        if let Some(temperature) = cfg.temperature {
+            options.insert("temperature".into(), temperature.into());
+        }
+        Self { cfg, client: Client::new(), api: OllamaApi::Generate, options }
+    }
+
+    pub fn with_api(mut self, api: OllamaApi) -> Self {
+        self.api = api;
+        self
+    }
+
+    /// Set a model option such as `temperature` or `num_ctx`.
+    pub fn with_option(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.options.insert(key.into(), value.into());
+        self
+    }
+
+    /// `/api/generate` cannot carry earlier turns, so those prompts always use chat.
+    fn api_for(&self, req: &Prompt) -> OllamaApi {
+        if req.turns.is_empty() { self.api } else { OllamaApi::Chat }
+    }
+
+    fn url(&self, api: OllamaApi) -> String {
+        let path = match api {
+            OllamaApi::Generate => "api/generate",
+            OllamaApi::Chat => "api/chat",
+        };
+        format!("{}/{path}", self.cfg.base_url.trim_end_matches('/'))
+    }
+
+    fn body(&self, req: &Prompt, stream: bool) -> Value {
+        let mut body = match self.api_for(req) {
+            OllamaApi::Generate => {
+                let mut body = json!({ "model": self.cfg.model, "prompt": req.user, "stream": stream });
+                if let Some(system) = &req.system {
+                    body["system"] = json!(system);
+                }
+                body
+            }
+            OllamaApi::Chat => json!({ "model": self.cfg.model, "messages": req.messages(), "stream": stream }),
+        };
+        if !self.options.is_empty() {
+            body["options"] = Value::Object(self.options.clone());
+        }
+        if let Some(schema) = &req.schema {
+            body["format"] = schema.clone();
+        }
+        body
+    }
+
+    async fn send(&self, req: &Prompt, stream: bool) -> Result<reqwest::Response> {
+        check(self.client.post(self.url(self.api_for(req))).json(&self.body(req, stream)).send().await?).await
+    }
+}
+
+/// Parse one NDJSON line into its text, whether it is the last one, and the
+/// token counts the last one carries.
+fn parse_chunk(line: &str) -> Result<(String, bool, Option<Usage>)> {
+    let chunk: Chunk = serde_json::from_str(line)?;
+    if let Some(err) = chunk.error {
+        bail!("ollama: {err}");
+    }
+    let text = match (chunk.response, chunk.message) {
+        (Some(r), _) => r,
+        (None, Some(m)) => m.content,
+        (None, None) => String::new(),
+    };
+    let usage = match (chunk.prompt_eval_count, chunk.eval_count) {
+        (None, None) => None,
+        (prompt, completion) => Some(Usage::new(prompt.unwrap_or(0), completion.unwrap_or(0))),
+    };
+    Ok((text, chunk.done, usage))
+}
+
+/// Collect the text of a reply, which may be one object or NDJSON chunks.
+fn collect(body: &str) -> Result<(String, Option<Usage>)> {
+    let mut text = String::new();
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let (delta, done, usage) = parse_chunk(line)?;
+        text.push_str(&delta);
+        if done {
+            return Ok((text, usage));
+        }
+    }
+    Err(anyhow!("ollama: response ended before done"))
+}
+
+/// Pass a successful response through, or turn it into an [`HttpError`].
+async fn check(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let retry_after = retry_after(resp.headers());
+    let body = resp.text().await?;
+    let message = serde_json::from_str::<Chunk>(&body)
+        .ok()
+        .and_then(|c| c.error)
+        .unwrap_or(body);
+    Err(HttpError { provider: "ollama".into(), status, message, retry_after }.into())
+}
+
+/// Reply of `/api/tags`.
+#[derive(Debug, Deserialize)]
+struct Tags {
+    #[serde(default)]
+    models: Vec<Tag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let body = self.send(&req, false).await?.text().await?;
+        let (text, usage) = collect(&body)?;
+        let usage = usage.unwrap_or_else(|| Usage::estimate(&req, &text));
+        Ok(Resp { text, usage: Some(usage) })
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let resp = self.send(&req, true).await?;
+        let slot = UsageSlot::default();
+        let usage = slot.clone();
+        Ok(deltas(lines(resp.bytes_stream()), move |line| {
+            if line.trim().is_empty() {
+                return Ok(Step::Skip);
+            }
+            Ok(match parse_chunk(line)? {
+                (text, true, reported) => {
+                    *usage.lock().unwrap() = reported;
+                    Step::Last(text)
+                }
+                (text, false, _) => Step::Delta(text),
+            })
+        })
+        .with_slot(slot))
+    }
+
+    fn supports_schema(&self) -> bool {
+        true
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/api/tags", self.cfg.base_url.trim_end_matches('/'));
+        let tags: Tags = check(self.client.get(url).send().await?).await?.json().await?;
+        Ok(tags.models.into_iter().map(|t| t.name).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Provider, Role};
+    use rstest::rstest;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn cfg(base_url: String) -> LlmConfig {
+        LlmConfig { provider: Provider::Ollama, base_url, api_key: None, model: "llama3".into(), ..Default::default() }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn generate_endpoint() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .and(body_partial_json(json!({
+                "model": "llama3",
+                "prompt": "list files",
+                "system": "be terse",
+                "stream": false,
+                "options": { "temperature": 0.0 }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3", "response": "ls -la", "done": true,
+                "prompt_eval_count": 26, "eval_count": 4
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = OllamaProvider::new(cfg(server.uri())).with_option("temperature", 0.0);
+        let prompt = Prompt::new("list files").with_system("be terse");
+        let resp = provider.complete(prompt).await.unwrap();
+        assert_eq!(resp.text, "ls -la");
+        assert_eq!(resp.usage, Some(Usage::new(26, 4)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn chat_endpoint() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({
+                "model": "llama3",
+                "messages": [
+                    { "role": "system", "content": "be terse" },
+                    { "role": "user", "content": "list files" }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3",
+                "message": { "role": "assistant", "content": "ls -la" },
+                "done": true
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = OllamaProvider::new(cfg(server.uri())).with_api(OllamaApi::Chat);
+        let prompt = Prompt::new("list files").with_system("be terse");
+        let resp = provider.complete(prompt.clone()).await.unwrap();
+        assert_eq!(resp.text, "ls -la");
+        assert_eq!(resp.usage, Some(Usage::estimate(&prompt, "ls -la")));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn schema_sent_as_format() {
+        let server = MockServer::start().await;
+        let schema = json!({ "type": "object", "properties": { "kind": { "type": "string" } } });
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .and(body_partial_json(json!({ "format": schema })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "response": "{\"kind\":\"command\"}", "done": true
+            })))
+            .mount(&server)
+            .await;
+
+        let resp = OllamaProvider::new(cfg(server.uri()))
+            .complete(Prompt::new("list files").with_schema(schema))
+            .await
+            .unwrap();
+        assert_eq!(resp.text, "{\"kind\":\"command\"}");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn turns_force_chat() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({
+                "messages": [
+                    { "role": "system", "content": "be terse" },
+                    { "role": "user", "content": "apt install git" },
+                    { "role": "assistant", "content": "apt-get install git" },
+                    { "role": "user", "content": "install curl" }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "message": { "role": "assistant", "content": "apt-get install curl" },
+                "done": true
+            })))
+            .mount(&server)
+            .await;
+
+        let prompt = Prompt::new("install curl")
+            .with_system("be terse")
+            .with_turn(Role::User, "apt install git")
+            .with_turn(Role::Assistant, "apt-get install git");
+        let resp = OllamaProvider::new(cfg(server.uri())).complete(prompt).await.unwrap();
+        assert_eq!(resp.text, "apt-get install curl");
+    }
+
+    #[rstest]
+    #[case("{\"response\":\"ls\",\"done\":false}\n{\"response\":\" -la\",\"done\":true}\n", Some("ls -la"))]
+    #[case("{\"response\":\"ls\",\"done\":false}\n", None)]
+    #[case("{\"error\":\"boom\"}", None)]
+    fn collect_chunks(#[case] body: &str, #[case] expected: Option<&str>) {
+        assert_eq!(collect(body).ok().map(|(text, _)| text).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn streams_ndjson() {
+        use futures_util::TryStreamExt;
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .and(body_partial_json(json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                "{\"response\":\"ls\",\"done\":false}\n{\"response\":\" -la\",\"done\":false}\n{\"response\":\"\",\"done\":true,\"prompt_eval_count\":30,\"eval_count\":3}\n",
+                "application/x-ndjson",
+            ))
+            .mount(&server)
+            .await;
+
+        let mut stream = OllamaProvider::new(cfg(server.uri()))
+            .complete_stream(Prompt::new("list files"))
+            .await
+            .unwrap();
+        let mut parts = Vec::new();
+        while let Some(part) = stream.try_next().await.unwrap() {
+            parts.push(part);
+        }
+        assert_eq!(parts.concat(), "ls -la");
+        assert_eq!(parts[0], "ls");
+        assert_eq!(stream.usage(), Some(Usage::new(30, 3)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn surfaces_server_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": "model 'nope' not found"
+            })))
+            .mount(&server)
+            .await;
+
+        let err = OllamaProvider::new(cfg(server.uri()))
+            .complete(Prompt::new("hi"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("model 'nope' not found"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn lists_installed_models() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "models": [{ "name": "llama3:latest", "size": 1 }, { "name": "phi3:mini", "size": 2 }]
+            })))
+            .mount(&server)
+            .await;
+
+        let models = OllamaProvider::new(cfg(server.uri())).list_models().await.unwrap();
+        assert_eq!(models, vec!["llama3:latest", "phi3:mini"]);
+        assert!(crate::has_model(&models, "llama3"));
+        assert!(!crate::has_model(&models, "phi3"));
+    }
+}
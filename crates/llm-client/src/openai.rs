@@ -0,0 +1,398 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::retry::{HttpError, retry_after};
+use crate::stream::{Step, TextStream, UsageSlot, deltas, lines};
+use crate::{LlmConfig, LlmProvider, Prompt, Provider, Resp, Usage};
+
+/// Sent to OpenRouter so requests are attributed to CLAppy.
+const REFERER: &str = "https://github.com/theadminautomated/CLAppy-cli";
+const TITLE: &str = "CLAppy";
+
+/// Provider for any OpenAI-shaped `/v1/chat/completions` endpoint
+/// (OpenRouter, vLLM, LM Studio, LiteLLM, ...).
+#[derive(Debug, Clone)]
+pub struct OpenAiProvider {
+    cfg: LlmConfig,
+    client: Client,
+}
+
+/// Chat completion body, shared with the Azure-hosted variant.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Completion {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<ApiUsage>,
+}
+
+/// Token counts as reported in `usage`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct ApiUsage {
+    #[serde(default)]
+    prompt_tokens: u32,
+    #[serde(default)]
+    completion_tokens: u32,
+}
+
+impl From<ApiUsage> for Usage {
+    fn from(u: ApiUsage) -> Self {
+        Usage::new(u.prompt_tokens, u.completion_tokens)
+    }
+}
+
+impl Completion {
+    pub(crate) fn usage(&self) -> Option<Usage> {
+        self.usage.map(Usage::from)
+    }
+
+    pub(crate) fn into_text(self) -> Option<String> {
+        self.choices.into_iter().next().and_then(|c| c.message.content)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// One `data:` event of a streamed completion.
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    /// Sent on the last chunk when `stream_options.include_usage` is set.
+    #[serde(default)]
+    usage: Option<ApiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// `response_format` requesting a reply that follows `schema`.
+pub(crate) fn response_format(schema: &Value) -> Value {
+    json!({
+        "type": "json_schema",
+        "json_schema": { "name": "clappy_action", "strict": true, "schema": schema }
+    })
+}
+
+/// Parse one line of a server-sent event stream of completion chunks.
+pub(crate) fn sse_step(line: &str, usage: &UsageSlot) -> Result<Step> {
+    let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+        return Ok(Step::Skip);
+    };
+    if data == "[DONE]" {
+        return Ok(Step::Done);
+    }
+    let chunk: StreamChunk = serde_json::from_str(data)?;
+    if let Some(reported) = chunk.usage {
+        *usage.lock().unwrap() = Some(reported.into());
+    }
+    Ok(match chunk.choices.into_iter().next().and_then(|c| c.delta.content) {
+        Some(text) if !text.is_empty() => Step::Delta(text),
+        _ => Step::Skip,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ApiError,
+}
+
+/// Reply of `/v1/models`.
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    #[serde(default)]
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(cfg: LlmConfig) -> Self {
+        Self { cfg, client: Client::new() }
+    }
+
+    /// `base_url` may be given with or without the trailing `/v1`.
+    fn v1(&self, path: &str) -> String {
+        let base = self.cfg.base_url.trim_end_matches('/');
+        let base = base.strip_suffix("/v1").unwrap_or(base);
+        format!("{base}/v1/{path}")
+    }
+
+    fn url(&self) -> String {
+        self.v1("chat/completions")
+    }
+
+    fn body(&self, req: &Prompt, stream: bool) -> Value {
+        let mut body = json!({
+            "model": self.cfg.model,
+            "messages": req.messages(),
+            "stream": stream,
+        });
+        if stream {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if let Some(temperature) = self.cfg.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(schema) = &req.schema {
+            body["response_format"] = response_format(schema);
+        }
+        body
+    }
+
+    /// Send with the key and, for OpenRouter, the attribution headers.
+    async fn send_authorized(&self, mut builder: RequestBuilder) -> Result<reqwest::Response> {
+        if let Some(key) = self.cfg.api_key.as_deref().filter(|k| !k.is_empty()) {
+            builder = builder.bearer_auth(key);
+        }
+        if matches!(self.cfg.provider, Provider::OpenRouter) {
+            builder = builder.header("HTTP-Referer", REFERER).header("X-Title", TITLE);
+        }
+        let resp = builder.send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = retry_after(resp.headers());
+            let body = resp.text().await?;
+            let message = serde_json::from_str::<ErrorBody>(&body)
+                .map(|e| e.error.message)
+                .unwrap_or(body);
+            return Err(HttpError { provider: self.cfg.provider.to_string(), status, message, retry_after }.into());
+        }
+        Ok(resp)
+    }
+
+    async fn send(&self, req: &Prompt, stream: bool) -> Result<reqwest::Response> {
+        self.send_authorized(self.client.post(self.url()).json(&self.body(req, stream))).await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        let body = self.send(&req, false).await?.text().await?;
+        let completion: Completion = serde_json::from_str(&body)?;
+        let usage = completion.usage();
+        let text = completion
+            .into_text()
+            .ok_or_else(|| anyhow!("{}: response has no choices", self.cfg.provider))?;
+        let usage = usage.unwrap_or_else(|| Usage::estimate(&req, &text));
+        Ok(Resp { text, usage: Some(usage) })
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        let resp = self.send(&req, true).await?;
+        let slot = UsageSlot::default();
+        let usage = slot.clone();
+        Ok(deltas(lines(resp.bytes_stream()), move |line| sse_step(line, &usage)).with_slot(slot))
+    }
+
+    /// Custom gateways such as vLLM or LM Studio may reject strict schemas.
+    fn supports_schema(&self) -> bool {
+        !matches!(self.cfg.provider, Provider::Custom)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let models: ModelList = self.send_authorized(self.client.get(self.v1("models"))).await?.json().await?;
+        Ok(models.data.into_iter().map(|m| m.id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn cfg(provider: Provider, base_url: String, api_key: Option<&str>) -> LlmConfig {
+        LlmConfig { provider, base_url, api_key: api_key.map(Into::into), model: "gpt-4o-mini".into(), ..Default::default() }
+    }
+
+    fn reply(text: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": text } }]
+        }))
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn openrouter_headers_and_auth() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("authorization", "Bearer sk-test"))
+            .and(header("x-title", TITLE))
+            .and(header("http-referer", REFERER))
+            .and(body_partial_json(json!({
+                "model": "gpt-4o-mini",
+                "messages": [{ "role": "user", "content": "list files" }]
+            })))
+            .respond_with(reply("ls -la"))
+            .mount(&server)
+            .await;
+
+        let provider = OpenAiProvider::new(cfg(Provider::OpenRouter, server.uri(), Some("sk-test")));
+        let resp = provider.complete(Prompt::new("list files")).await.unwrap();
+        assert_eq!(resp.text, "ls -la");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sends_profile_temperature() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "temperature": 0.5 })))
+            .respond_with(reply("pwd"))
+            .mount(&server)
+            .await;
+
+        let cfg = LlmConfig { temperature: Some(0.5), ..cfg(Provider::Custom, server.uri(), None) };
+        let resp = OpenAiProvider::new(cfg).complete(Prompt::new("where am i")).await.unwrap();
+        assert_eq!(resp.text, "pwd");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn lists_models_with_auth() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .and(header("authorization", "Bearer sk-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "data": [{ "id": "gpt-4o", "object": "model" }, { "id": "gpt-4o-mini", "object": "model" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let base = format!("{}/v1", server.uri());
+        let models = OpenAiProvider::new(cfg(Provider::OpenRouter, base, Some("sk-test"))).list_models().await.unwrap();
+        assert_eq!(models, vec!["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn custom_base_with_v1_suffix() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(reply("pwd"))
+            .mount(&server)
+            .await;
+
+        let base = format!("{}/v1/", server.uri());
+        let provider = OpenAiProvider::new(cfg(Provider::Custom, base, Some("")));
+        let resp = provider.complete(Prompt::new("where am i")).await.unwrap();
+        assert_eq!(resp.text, "pwd");
+        let req = &server.received_requests().await.unwrap()[0];
+        assert!(req.headers.get("authorization").is_none());
+        assert!(req.headers.get("x-title").is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn schema_sent_as_response_format() {
+        let server = MockServer::start().await;
+        let schema = json!({ "type": "object" });
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({
+                "response_format": { "type": "json_schema", "json_schema": { "strict": true, "schema": schema } }
+            })))
+            .respond_with(reply("{}"))
+            .mount(&server)
+            .await;
+
+        let provider = OpenAiProvider::new(cfg(Provider::Custom, server.uri(), None));
+        let resp = provider.complete(Prompt::new("hi").with_schema(schema)).await.unwrap();
+        assert_eq!(resp.text, "{}");
+    }
+
+    #[rstest]
+    #[case(Provider::OpenRouter, true)]
+    #[case(Provider::Custom, false)]
+    fn schema_support_by_provider(#[case] provider: Provider, #[case] expected: bool) {
+        assert_eq!(OpenAiProvider::new(cfg(provider, "http://localhost".into(), None)).supports_schema(), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn streams_sse() {
+        use futures_util::TryStreamExt;
+
+        let server = MockServer::start().await;
+        let body = [
+            ": keep-alive",
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" -la\"}}]}",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":21,\"completion_tokens\":2,\"total_tokens\":23}}",
+            "data: [DONE]",
+        ]
+        .join("\n\n");
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(json!({ "stream": true, "stream_options": { "include_usage": true } })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let mut stream = OpenAiProvider::new(cfg(Provider::Custom, server.uri(), None))
+            .complete_stream(Prompt::new("list files"))
+            .await
+            .unwrap();
+        let mut parts = Vec::new();
+        while let Some(part) = stream.try_next().await.unwrap() {
+            parts.push(part);
+        }
+        assert_eq!(parts, vec!["ls", " -la"]);
+        assert_eq!(stream.usage(), Some(Usage::new(21, 2)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn surfaces_api_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": { "message": "invalid api key", "code": 401 }
+            })))
+            .mount(&server)
+            .await;
+
+        let err = OpenAiProvider::new(cfg(Provider::OpenRouter, server.uri(), Some("bad")))
+            .complete(Prompt::new("hi"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "openrouter: invalid api key (401 Unauthorized)");
+    }
+}
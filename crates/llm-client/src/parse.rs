@@ -0,0 +1,148 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+
+/// A command extracted from a model reply.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ParsedCommand {
+    #[serde(alias = "cmd")]
+    pub command: String,
+    #[serde(default, alias = "explanation")]
+    pub rationale: Option<String>,
+}
+
+/// The reply contained no recognisable command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoCommand {
+    pub reply: String,
+}
+
+impl fmt::Display for NoCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no command found in model reply")
+    }
+}
+
+impl std::error::Error for NoCommand {}
+
+static FENCE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)```[ \t]*([A-Za-z0-9_+-]*)[ \t]*\r?\n(.*?)```").unwrap());
+static INLINE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`\n]+)`").unwrap());
+static LEAD_IN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(sure|certainly|of course|here|to |you can|you could|you should|this |that |the |it |i |i'm|okay|ok[,!. ]|note|explanation|rationale|alternatively)")
+        .unwrap()
+});
+static PROMPT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?:\$|>)\s+").unwrap());
+/// A capitalised English word, as prose starts with and commands rarely do.
+static PROSE_WORD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z][a-z]+(?:'[a-z]+)?[,:]?$").unwrap());
+/// Flags, paths, operators and file names that only a command would carry.
+static SHELL_HINT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|\s)--?\w|[/~|<>=$*&]|\w\.\w").unwrap());
+
+/// Whether a line reads like prose rather than a shell command.
+fn is_prose(line: &str) -> bool {
+    let words = line.split_whitespace().count();
+    let first = line.split_whitespace().next().unwrap_or("");
+    line.starts_with('#')
+        || line.ends_with(':')
+        || LEAD_IN_RE.is_match(line)
+        || (words >= 5 && line.ends_with(['.', '!', '?']))
+        || (PROSE_WORD_RE.is_match(first) && !SHELL_HINT_RE.is_match(line))
+}
+
+/// Drop a leading `Output:` label or `$ ` prompt marker.
+fn clean(line: &str) -> String {
+    let line = line.trim();
+    let line = line.strip_prefix("Output:").map(str::trim).unwrap_or(line);
+    PROMPT_RE.replace(line, "").trim().to_string()
+}
+
+fn rationale(prose: &str) -> Option<String> {
+    let lines: Vec<&str> = prose
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.ends_with(':'))
+        .collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+fn from_json(text: &str) -> Option<ParsedCommand> {
+    serde_json::from_str::<ParsedCommand>(text.trim())
+        .ok()
+        .filter(|p| !p.command.trim().is_empty())
+}
+
+/// Extract the command to run, and any rationale, from a model reply.
+///
+/// Understands JSON `{command, rationale}` objects, fenced code blocks,
+/// `Output:` prefixes, inline backticks and a bare command line.
+pub fn parse_command(reply: &str) -> Result<ParsedCommand, NoCommand> {
+    if let Some(parsed) = from_json(reply) {
+        return Ok(parsed);
+    }
+    if let Some(cap) = FENCE_RE.captures(reply) {
+        let body = cap.get(2).map_or("", |m| m.as_str());
+        if cap.get(1).map(|m| m.as_str()) == Some("json")
+            && let Some(parsed) = from_json(body)
+        {
+            return Ok(parsed);
+        }
+        let command = body.lines().map(clean).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+        if !command.is_empty() {
+            let whole = cap.get(0).unwrap();
+            let prose = format!("{}\n{}", &reply[..whole.start()], &reply[whole.end()..]);
+            return Ok(ParsedCommand { command, rationale: rationale(&prose) });
+        }
+    }
+    let lines: Vec<&str> = reply.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    for (i, line) in lines.iter().enumerate() {
+        if is_prose(line) {
+            if let Some(cap) = INLINE_RE.captures(line) {
+                return Ok(ParsedCommand { command: clean(&cap[1]), rationale: rationale(&lines.join("\n")) });
+            }
+            continue;
+        }
+        let command = clean(line);
+        if command.is_empty() {
+            continue;
+        }
+        let prose: Vec<&str> = lines.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, l)| *l).collect();
+        return Ok(ParsedCommand { command, rationale: rationale(&prose.join("\n")) });
+    }
+    Err(NoCommand { reply: reply.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("ls -la", "ls -la", None)]
+    #[case("  Output: ls -la\n", "ls -la", None)]
+    #[case("$ ls -la", "ls -la", None)]
+    #[case("# Careful, this deletes files\nrm -rf build", "rm -rf build", Some("# Careful, this deletes files"))]
+    #[case("Get-ChildItem -Recurse", "Get-ChildItem -Recurse", None)]
+    #[case("Sure! Here's the command:\n```bash\nls -la\n```\nThis lists all files.", "ls -la", Some("This lists all files."))]
+    #[case("```\n$ apt-get install git\n```", "apt-get install git", None)]
+    #[case("{\"command\": \"du -sh .\", \"rationale\": \"disk usage\"}", "du -sh .", Some("disk usage"))]
+    #[case("```json\n{\"cmd\": \"df -h\"}\n```", "df -h", None)]
+    #[case("You can run `git status` to see changes.", "git status", Some("You can run `git status` to see changes."))]
+    #[case("Sure, here it is:\nfind . -name '*.rs'\nIt searches recursively.", "find . -name '*.rs'", Some("It searches recursively."))]
+    fn extracts(#[case] reply: &str, #[case] command: &str, #[case] rationale: Option<&str>) {
+        let parsed = parse_command(reply).unwrap();
+        assert_eq!(parsed.command, command);
+        assert_eq!(parsed.rationale.as_deref(), rationale);
+    }
+
+    #[rstest]
+    #[case("I'm not sure what you mean. Could you clarify the request?")]
+    #[case("Here is what I would do:")]
+    #[case("   ")]
+    #[case("# Note: this is dangerous")]
+    #[case("Unfortunately I cannot do that")]
+    #[case("Cannot determine the command.")]
+    fn rejects_prose(#[case] reply: &str) {
+        assert_eq!(parse_command(reply), Err(NoCommand { reply: reply.into() }));
+    }
+}
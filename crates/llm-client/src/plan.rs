@@ -0,0 +1,191 @@
+use anyhow::{Result, bail};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{Intent, LlmProvider, NoCommand, ParsedCommand, Role, build_prompt, parse_command};
+
+/// One command of a [`Plan`] and why it is needed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    #[serde(alias = "cmd")]
+    pub command: String,
+    #[serde(default)]
+    pub rationale: Option<String>,
+}
+
+/// Commands to run in order to fulfil a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+static LIST_ITEM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*(?:\d+[.)]|[-*])\s+(.+)$").unwrap());
+static QUOTED_ITEM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^`([^`]+)`(?:\s*[-:–—]?\s*(.*))?$").unwrap());
+static THEN_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)[\s,;]*\bthen\b\s*").unwrap());
+
+/// Whether `line` asks for things in sequence with an explicit "then", e.g.
+/// "create a venv, then run the tests". Lists such as "files, folders and
+/// links" are usually one command, so commas and "and" alone do not count.
+pub fn looks_multi_step(line: &str) -> bool {
+    THEN_RE.split(line.trim()).filter(|p| !p.trim().is_empty()).count() >= 2
+}
+
+/// JSON schema for plan replies.
+pub fn plan_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "steps": {
+                "type": "array",
+                "minItems": 1,
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "string" },
+                        "rationale": { "type": "string" }
+                    },
+                    "required": ["command", "rationale"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["steps"],
+        "additionalProperties": false
+    })
+}
+
+const INSTRUCTIONS: &str = "The request needs several commands. Reply with a single JSON object \
+whose `steps` array lists them in the order they must run; each step has the shell `command` \
+and a short `rationale`. Each command runs in a fresh shell from the working directory.";
+
+fn from_json(reply: &str) -> Option<Plan> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    serde_json::from_str::<Plan>(&reply[start..=end]).ok()
+}
+
+/// A list item: `` `cmd` - why `` or anything [`parse_command`] understands.
+fn list_step(item: &str) -> Option<ParsedCommand> {
+    if let Some(cap) = QUOTED_ITEM_RE.captures(item) {
+        let rationale = cap.get(2).map(|m| m.as_str().to_string()).filter(|r| !r.is_empty());
+        return Some(ParsedCommand { command: cap[1].to_string(), rationale });
+    }
+    parse_command(item).ok()
+}
+
+/// Extract the steps from a model reply: a `{steps: [...]}` object, a
+/// numbered or bulleted list of commands, or a single command.
+pub fn parse_plan(reply: &str) -> Result<Plan> {
+    let plan = match from_json(reply) {
+        Some(plan) => plan,
+        None => {
+            let items: Vec<&str> =
+                reply.lines().filter_map(|l| LIST_ITEM_RE.captures(l)).map(|c| c.get(1).unwrap().as_str()).collect();
+            let steps = if items.is_empty() {
+                vec![parse_command(reply)?]
+            } else {
+                items.into_iter().filter_map(list_step).collect()
+            };
+            let steps = steps.into_iter().map(|p| Step { command: p.command, rationale: p.rationale }).collect();
+            Plan { steps }
+        }
+    };
+    let steps: Vec<Step> = plan
+        .steps
+        .into_iter()
+        .map(|s| Step {
+            command: s.command.trim().to_string(),
+            rationale: s.rationale.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+        })
+        .filter(|s| !s.command.is_empty())
+        .collect();
+    if steps.is_empty() {
+        bail!(NoCommand { reply: reply.to_string() });
+    }
+    Ok(Plan { steps })
+}
+
+/// Ask the provider to break `text` into an ordered list of commands.
+pub async fn infer_plan<P: LlmProvider + ?Sized>(provider: &P, text: &str, context: Option<&str>) -> Result<Plan> {
+    let mut prompt = build_prompt(Intent::Translate, text, context, None);
+    if let Some(system) = prompt.system.as_mut() {
+        system.push_str("\n\n");
+        system.push_str(INSTRUCTIONS);
+    }
+    // Few-shot answers are bare commands; restate them as one-step plans.
+    for turn in prompt.turns.iter_mut().filter(|t| t.role == Role::Assistant) {
+        turn.content = json!({ "steps": [{ "command": turn.content, "rationale": "" }] }).to_string();
+    }
+    let resp = provider.complete(prompt.with_schema(plan_schema())).await?;
+    parse_plan(&resp.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Prompt, Resp};
+    use async_trait::async_trait;
+    use rstest::rstest;
+
+    fn step(command: &str, rationale: Option<&str>) -> Step {
+        Step { command: command.into(), rationale: rationale.map(Into::into) }
+    }
+
+    #[rstest]
+    #[case("create a venv, then install requirements and run tests", true)]
+    #[case("build the image then push it", true)]
+    #[case("create a venv, install requirements and run tests", false)]
+    #[case("list files, folders and symlinks", false)]
+    #[case("list files and folders", false)]
+    #[case("then what", false)]
+    #[case("show disk usage", false)]
+    fn detects_multi_step(#[case] line: &str, #[case] expected: bool) {
+        assert_eq!(looks_multi_step(line), expected);
+    }
+
+    #[rstest]
+    #[case(
+        r#"{"steps":[{"command":"python3 -m venv .venv","rationale":"isolate deps"},{"command":".venv/bin/pip install -r requirements.txt","rationale":" "}]}"#,
+        vec![step("python3 -m venv .venv", Some("isolate deps")), step(".venv/bin/pip install -r requirements.txt", None)]
+    )]
+    #[case(
+        "Here is the plan:\n1. `git fetch origin` - get the latest main\n2. `git rebase origin/main`\n",
+        vec![step("git fetch origin", Some("get the latest main")), step("git rebase origin/main", None)]
+    )]
+    #[case("- make build\n- make test", vec![step("make build", None), step("make test", None)])]
+    #[case("ls -la", vec![step("ls -la", None)])]
+    fn parses(#[case] reply: &str, #[case] expected: Vec<Step>) {
+        assert_eq!(parse_plan(reply).unwrap().steps, expected);
+    }
+
+    #[rstest]
+    #[case(r#"{"steps":[]}"#)]
+    #[case(r#"{"steps":[{"command":"  ","rationale":"nothing"}]}"#)]
+    fn rejects_empty_plans(#[case] reply: &str) {
+        assert!(parse_plan(reply).unwrap_err().is::<NoCommand>());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn infer_sends_schema() {
+        struct PlanProvider;
+
+        #[async_trait]
+        impl LlmProvider for PlanProvider {
+            async fn complete(&self, req: Prompt) -> Result<Resp> {
+                assert_eq!(req.schema, Some(plan_schema()));
+                assert!(req.system.as_deref().unwrap().contains("`steps` array"));
+                assert!(req.turns.iter().filter(|t| t.role == Role::Assistant).all(|t| t.content.starts_with('{')));
+                Ok(Resp {
+                    text: r#"{"steps":[{"command":"make","rationale":"build"},{"command":"make test","rationale":"test"}]}"#.into(),
+                    usage: None,
+                })
+            }
+        }
+
+        let plan = infer_plan(&PlanProvider, "build and test, then tell me", None).await.unwrap();
+        assert_eq!(plan.steps, vec![step("make", Some("build")), step("make test", Some("test"))]);
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Result, ensure};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// Package managers in order of preference, with how each installs `git`.
+const PACKAGE_MANAGERS: &[(&str, &str)] = &[
+    ("apt-get", "sudo apt-get install -y git"),
+    ("dnf", "sudo dnf install -y git"),
+    ("yum", "sudo yum install -y git"),
+    ("pacman", "sudo pacman -S git"),
+    ("zypper", "sudo zypper install git"),
+    ("apk", "sudo apk add git"),
+    ("brew", "brew install git"),
+    ("port", "sudo port install git"),
+    ("winget", "winget install --id Git.Git -e"),
+    ("choco", "choco install git"),
+    ("scoop", "scoop install git"),
+    ("nix-env", "nix-env -iA nixpkgs.git"),
+];
+
+/// Tools worth telling the model about when present.
+const TOOLS: &[&str] = &[
+    "git", "docker", "podman", "kubectl", "curl", "wget", "jq", "rg", "fd", "make", "python3", "node", "npm", "cargo",
+    "go", "java", "systemctl",
+];
+
+static VERSION_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+\.\d+(?:\.\d+)?").unwrap());
+
+/// What the local machine looks like, reduced to facts safe to send to a model:
+/// names and versions, never raw paths or variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Environment {
+    pub os: String,
+    /// Distribution or OS release, e.g. `Ubuntu 24.04 LTS`.
+    pub distro: Option<String>,
+    pub shell: Option<String>,
+    pub shell_version: Option<String>,
+    /// Working directory with the home directory shown as `~`; outside home,
+    /// only its last component, e.g. `.../app`.
+    pub cwd: Option<String>,
+    pub package_managers: Vec<String>,
+    pub tools: Vec<String>,
+}
+
+/// `PRETTY_NAME` (or `NAME`) from an `/etc/os-release` file.
+fn os_release(text: &str) -> Option<String> {
+    let field = |key: &str| {
+        text.lines()
+            .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+            .map(|v| v.trim().trim_matches('"').to_string())
+            .filter(|v| !v.is_empty())
+    };
+    field("PRETTY_NAME").or_else(|| field("NAME"))
+}
+
+fn distro() -> Option<String> {
+    match std::env::consts::OS {
+        "linux" => os_release(&std::fs::read_to_string("/etc/os-release").ok()?),
+        "macos" => {
+            let out = Command::new("sw_vers").arg("-productVersion").output().ok()?;
+            Some(format!("macOS {}", String::from_utf8_lossy(&out.stdout).trim()))
+        }
+        _ => None,
+    }
+}
+
+fn shell() -> Option<String> {
+    let path = if cfg!(windows) { std::env::var("ComSpec").ok()? } else { std::env::var("SHELL").ok()? };
+    let name = Path::new(&path).file_stem()?.to_string_lossy().to_lowercase();
+    Some(name).filter(|n| !n.is_empty())
+}
+
+fn shell_version(shell: &str) -> Option<String> {
+    if cfg!(windows) {
+        return None;
+    }
+    let out = Command::new(shell).arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    VERSION_RE.find(text.lines().next()?).map(|m| m.as_str().to_string())
+}
+
+/// `cwd` as safe to share: relative to `home` when inside it, otherwise
+/// reduced to its last component.
+fn shareable_cwd(cwd: &str, home: &str) -> String {
+    match cwd.strip_prefix(home.trim_end_matches(['/', '\\'])) {
+        Some(rest) if home.len() > 1 && (rest.is_empty() || rest.starts_with(['/', '\\'])) => format!("~{rest}"),
+        _ => match Path::new(cwd).file_name() {
+            Some(name) => format!(".../{}", name.to_string_lossy()),
+            None => cwd.to_string(),
+        },
+    }
+}
+
+fn cwd() -> Option<String> {
+    let cwd = std::env::current_dir().ok()?.to_string_lossy().into_owned();
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).unwrap_or_default();
+    Some(shareable_cwd(&cwd, &home))
+}
+
+/// Which of `names` are executables somewhere on `path`, in the order given.
+fn on_path(path: &OsStr, names: &[&str]) -> Vec<String> {
+    let dirs: Vec<_> = std::env::split_paths(path).collect();
+    let exts: &[&str] = if cfg!(windows) { &["exe", "cmd", "bat"] } else { &[""] };
+    names
+        .iter()
+        .filter(|name| {
+            dirs.iter().any(|dir| exts.iter().any(|ext| dir.join(name).with_extension(ext).is_file()))
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+impl Environment {
+    /// Look at the current machine. Runs `$SHELL --version`, so call it once.
+    pub fn probe() -> Self {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let managers: Vec<&str> = PACKAGE_MANAGERS.iter().map(|(name, _)| *name).collect();
+        let shell = shell();
+        Self {
+            os: std::env::consts::OS.to_string(),
+            distro: distro(),
+            shell_version: shell.as_deref().and_then(shell_version),
+            shell,
+            cwd: cwd(),
+            package_managers: on_path(&path, &managers),
+            tools: on_path(&path, TOOLS),
+        }
+    }
+
+    /// One fact per line for the system prompt.
+    pub fn facts(&self) -> String {
+        let unknown = || "unknown".to_string();
+        let os = match &self.distro {
+            Some(distro) => format!("{} ({distro})", self.os),
+            None => self.os.clone(),
+        };
+        let shell = match (&self.shell, &self.shell_version) {
+            (Some(shell), Some(version)) => format!("{shell} {version}"),
+            (Some(shell), None) => shell.clone(),
+            (None, _) => unknown(),
+        };
+        let list = |items: &[String]| if items.is_empty() { "none found".to_string() } else { items.join(", ") };
+        format!(
+            "OS: {os}\nShell: {shell}\nWorking directory: {}\nPackage managers: {}\nTools: {}",
+            self.cwd.clone().unwrap_or_else(unknown),
+            list(&self.package_managers),
+            list(&self.tools),
+        )
+    }
+
+    /// How to install `git` with the preferred package manager found.
+    pub fn install_example(&self) -> Option<&'static str> {
+        PACKAGE_MANAGERS
+            .iter()
+            .find(|(name, _)| self.package_managers.iter().any(|m| m == name))
+            .map(|(_, cmd)| *cmd)
+    }
+}
+
+static PROBED: Lazy<Environment> = Lazy::new(Environment::probe);
+static PINNED: OnceCell<Environment> = OnceCell::new();
+
+/// The environment prompts describe: the pinned one, else this process's,
+/// probed on first use.
+pub fn environment() -> &'static Environment {
+    PINNED.get().unwrap_or(&PROBED)
+}
+
+/// Describe `env` instead of this machine from now on, so prompts match a
+/// session recorded elsewhere. Fails if a different one is already pinned.
+pub fn pin_environment(env: Environment) -> Result<()> {
+    let pinned = PINNED.get_or_init(|| env.clone());
+    ensure!(*pinned == env, "prompts are already pinned to another environment");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 24.04 LTS\"\nID=ubuntu\n", Some("Ubuntu 24.04 LTS"))]
+    #[case("NAME=Alpine Linux\nID=alpine\n", Some("Alpine Linux"))]
+    #[case("ID=weird\n", None)]
+    fn parses_os_release(#[case] text: &str, #[case] expected: Option<&str>) {
+        assert_eq!(os_release(text).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("/home/ada/src/app", "/home/ada", "~/src/app")]
+    #[case("/home/ada", "/home/ada/", "~")]
+    #[case("/home/adam/src", "/home/ada", ".../src")]
+    #[case("/srv/clients/acme/app", "/home/ada", ".../app")]
+    #[case("/", "", "/")]
+    fn shares_only_relative_cwd(#[case] cwd: &str, #[case] home: &str, #[case] expected: &str) {
+        assert_eq!(shareable_cwd(cwd, home), expected);
+    }
+
+    #[rstest]
+    fn finds_tools_on_path() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let exe = if cfg!(windows) { ".exe" } else { "" };
+        std::fs::write(a.path().join(format!("dnf{exe}")), "").unwrap();
+        std::fs::write(b.path().join(format!("git{exe}")), "").unwrap();
+        std::fs::create_dir(b.path().join(format!("apt-get{exe}"))).unwrap();
+        let path = std::env::join_paths([a.path(), b.path()]).unwrap();
+        assert_eq!(on_path(&path, &["apt-get", "dnf", "git", "jq"]), vec!["dnf", "git"]);
+    }
+
+    #[rstest]
+    fn facts_and_install_example() {
+        let env = Environment {
+            os: "linux".into(),
+            distro: Some("Fedora Linux 40".into()),
+            shell: Some("zsh".into()),
+            shell_version: Some("5.9".into()),
+            cwd: Some("~/src".into()),
+            package_managers: vec!["dnf".into(), "nix-env".into()],
+            tools: vec![],
+        };
+        assert_eq!(
+            env.facts(),
+            "OS: linux (Fedora Linux 40)\nShell: zsh 5.9\nWorking directory: ~/src\nPackage managers: dnf, nix-env\nTools: none found"
+        );
+        assert_eq!(env.install_example(), Some("sudo dnf install -y git"));
+        assert_eq!(Environment::default().install_example(), None);
+    }
+
+    #[rstest]
+    fn pins_one_environment() {
+        // Pinning what is already described leaves prompts unchanged for other tests.
+        let here = environment().clone();
+        pin_environment(here.clone()).unwrap();
+        assert_eq!(environment(), &here);
+        let elsewhere = Environment { os: "plan9".into(), ..Default::default() };
+        assert!(pin_environment(elsewhere).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::stream::TextStream;
+use crate::{FoundryError, LlmConfig, LlmProvider, ModelsUnsupported, Prompt, Resp};
+
+/// A non-success HTTP response from a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    pub provider: String,
+    pub status: StatusCode,
+    pub message: String,
+    /// Parsed `Retry-After` header, if the server sent one.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.provider, self.message, self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Read a `Retry-After` header given in seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    parse_retry_after(headers.get(RETRY_AFTER)?.to_str().ok()?, SystemTime::now())
+}
+
+/// A `Retry-After` value as a wait from `now`; dates already past mean no wait.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(secs).filter(|s| s.is_finite() && *s >= 0.0).map(Duration::from_secs_f64);
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Whether a failed request is worth trying again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Rate limited, server error or timeout; wait the given time if the server asked for one.
+    Retry(Option<Duration>),
+    /// Bad request, auth failure and the like; retrying will not help.
+    Fatal,
+}
+
+fn status_verdict(status: StatusCode, retry_after: Option<Duration>) -> Verdict {
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT || status.is_server_error() {
+        Verdict::Retry(retry_after)
+    } else {
+        Verdict::Fatal
+    }
+}
+
+/// Decide whether `err` is transient.
+pub fn verdict(err: &anyhow::Error) -> Verdict {
+    if let Some(e) = err.downcast_ref::<HttpError>() {
+        return status_verdict(e.status, e.retry_after);
+    }
+    if let Some(e) = err.downcast_ref::<FoundryError>() {
+        return status_verdict(e.status, e.retry_after);
+    }
+    if err.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        return Verdict::Retry(None);
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        if e.is_timeout() || e.is_connect() {
+            return Verdict::Retry(None);
+        }
+        if let Some(status) = e.status() {
+            return status_verdict(status, None);
+        }
+    }
+    Verdict::Fatal
+}
+
+/// How hard to try each provider before falling through to the next.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retries per provider after the first attempt.
+    pub max_retries: u32,
+    /// First backoff delay; doubles on every retry.
+    pub backoff_ms: u64,
+    /// Longest wait between attempts. A longer `Retry-After` skips to the next provider.
+    pub max_backoff_ms: u64,
+    /// Per-attempt timeout, until the reply (or the first streamed byte) arrives.
+    pub timeout_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 2, backoff_ms: 500, max_backoff_ms: 10_000, timeout_ms: 60_000 }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (0-based), or `None` to give up on this provider.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        let max = Duration::from_millis(self.max_backoff_ms);
+        match retry_after {
+            Some(wait) if wait > max => None,
+            Some(wait) => Some(wait),
+            None => Some(Duration::from_millis(self.backoff_ms.saturating_mul(1 << retry.min(20))).min(max)),
+        }
+    }
+}
+
+/// Tries an ordered chain of providers, retrying transient failures with
+/// backoff before falling through to the next one.
+pub struct FallbackProvider {
+    chain: Vec<(LlmConfig, Box<dyn LlmProvider>)>,
+    policy: RetryPolicy,
+    answered: Mutex<Option<usize>>,
+}
+
+impl FallbackProvider {
+    pub fn new(chain: Vec<(LlmConfig, Box<dyn LlmProvider>)>, policy: RetryPolicy) -> Self {
+        Self { chain, policy, answered: Mutex::new(None) }
+    }
+
+    async fn attempt<'a, T, F, Fut>(&'a self, call: F) -> Result<T>
+    where
+        F: Fn(&'a dyn LlmProvider) -> Fut,
+        Fut: Future<Output = Result<T>> + 'a,
+    {
+        let timeout = Duration::from_millis(self.policy.timeout_ms);
+        let mut errors = Vec::new();
+        for (i, (cfg, provider)) in self.chain.iter().enumerate() {
+            let mut retry = 0;
+            loop {
+                let result = match tokio::time::timeout(timeout, call(provider.as_ref())).await {
+                    Ok(result) => result,
+                    Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!("{}: timed out", cfg.provider))),
+                };
+                let err = match result {
+                    Ok(value) => {
+                        *self.answered.lock().unwrap() = Some(i);
+                        return Ok(value);
+                    }
+                    Err(err) => err,
+                };
+                let wait = match verdict(&err) {
+                    Verdict::Retry(retry_after) => self.policy.delay(retry, retry_after),
+                    Verdict::Fatal => None,
+                };
+                match wait {
+                    Some(wait) => {
+                        tokio::time::sleep(wait).await;
+                        retry += 1;
+                    }
+                    None => {
+                        errors.push(format!("{}/{}: {err:#}", cfg.provider, cfg.model));
+                        break;
+                    }
+                }
+            }
+        }
+        Err(anyhow!("all providers failed:\n  {}", errors.join("\n  ")))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for FallbackProvider {
+    async fn complete(&self, req: Prompt) -> Result<Resp> {
+        self.attempt(|p| p.complete(req.clone())).await
+    }
+
+    async fn complete_stream(&self, req: Prompt) -> Result<TextStream> {
+        self.attempt(|p| p.complete_stream(req.clone())).await
+    }
+
+    fn answered_by(&self) -> Option<LlmConfig> {
+        let i = (*self.answered.lock().unwrap())?;
+        self.chain.get(i).map(|(cfg, provider)| provider.answered_by().unwrap_or_else(|| cfg.clone()))
+    }
+
+    /// Only when every provider in the chain does, as any of them may answer.
+    fn supports_schema(&self) -> bool {
+        self.chain.iter().all(|(_, provider)| provider.supports_schema())
+    }
+
+    /// Models of the primary provider, which `/model` switches.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        match self.chain.first() {
+            Some((_, provider)) => provider.list_models().await,
+            None => Err(ModelsUnsupported.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OllamaProvider, OpenAiProvider, Provider};
+    use rstest::rstest;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy { max_retries: 2, backoff_ms: 1, max_backoff_ms: 50, timeout_ms: 1_000 }
+    }
+
+    fn ollama(base_url: String) -> (LlmConfig, Box<dyn LlmProvider>) {
+        let cfg = LlmConfig { provider: Provider::Ollama, base_url, model: "llama3".into(), ..Default::default() };
+        (cfg.clone(), Box::new(OllamaProvider::new(cfg)))
+    }
+
+    fn openai(base_url: String) -> (LlmConfig, Box<dyn LlmProvider>) {
+        let cfg = LlmConfig { provider: Provider::Custom, base_url, model: "gpt-4o-mini".into(), ..Default::default() };
+        (cfg.clone(), Box::new(OpenAiProvider::new(cfg)))
+    }
+
+    #[rstest]
+    #[case(None, 0, Some(1))]
+    #[case(None, 1, Some(2))]
+    #[case(None, 2, None)]
+    #[case(Some(30), 0, Some(30))]
+    #[case(Some(60), 0, None)]
+    fn delays(#[case] retry_after_ms: Option<u64>, #[case] retry: u32, #[case] expected_ms: Option<u64>) {
+        let wait = fast().delay(retry, retry_after_ms.map(Duration::from_millis));
+        assert_eq!(wait, expected_ms.map(Duration::from_millis));
+    }
+
+    #[rstest]
+    #[case("2", Some(2_000))]
+    #[case(" 0.5 ", Some(500))]
+    #[case("-1", None)]
+    #[case("Wed, 21 Oct 2015 07:28:30 GMT", Some(30_000))]
+    #[case("Wed, 21 Oct 2015 07:27:00 GMT", Some(0))]
+    #[case("soon", None)]
+    fn parses_retry_after(#[case] value: &str, #[case] expected_ms: Option<u64>) {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(parse_retry_after(value, now), expected_ms.map(Duration::from_millis));
+    }
+
+    #[rstest]
+    #[case(429, Verdict::Retry(Some(Duration::from_secs(2))))]
+    #[case(503, Verdict::Retry(Some(Duration::from_secs(2))))]
+    #[case(401, Verdict::Fatal)]
+    fn verdicts(#[case] status: u16, #[case] expected: Verdict) {
+        let err = HttpError {
+            provider: "custom".into(),
+            status: StatusCode::from_u16(status).unwrap(),
+            message: "nope".into(),
+            retry_after: Some(Duration::from_secs(2)),
+        };
+        assert_eq!(verdict(&err.into()), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retries_then_succeeds() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/generate"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "response": "ls", "done": true })))
+            .mount(&server)
+            .await;
+
+        let provider = FallbackProvider::new(vec![ollama(server.uri())], fast());
+        assert_eq!(provider.complete(Prompt::new("list")).await.unwrap().text, "ls");
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+        assert_eq!(provider.answered_by().unwrap().model, "llama3");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn falls_through_to_next_provider() {
+        let down = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503).set_body_json(json!({ "error": "overloaded" })))
+            .mount(&down)
+            .await;
+        let up = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": "pwd" } }]
+            })))
+            .mount(&up)
+            .await;
+
+        let provider = FallbackProvider::new(vec![ollama(down.uri()), openai(up.uri())], fast());
+        assert_eq!(provider.complete(Prompt::new("where am i")).await.unwrap().text, "pwd");
+        assert_eq!(down.received_requests().await.unwrap().len(), 3);
+        let answered = provider.answered_by().unwrap();
+        assert_eq!((answered.provider.to_string(), answered.model), ("custom".into(), "gpt-4o-mini".into()));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fatal_errors_skip_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({ "error": { "message": "bad key" } })))
+            .mount(&server)
+            .await;
+
+        let provider = FallbackProvider::new(vec![openai(server.uri())], fast());
+        let err = provider.complete(Prompt::new("hi")).await.unwrap_err();
+        assert!(err.to_string().contains("custom/gpt-4o-mini: custom: bad key (401 Unauthorized)"));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+        assert!(provider.answered_by().is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn timeouts_are_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "response": "late", "done": true }))
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&server)
+            .await;
+
+        let policy = RetryPolicy { timeout_ms: 50, max_retries: 1, ..fast() };
+        let provider = FallbackProvider::new(vec![ollama(server.uri())], policy);
+        let err = provider.complete(Prompt::new("hi")).await.unwrap_err();
+        assert!(err.to_string().contains("ollama: timed out"));
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Result;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::Usage;
+
+/// Where a stream leaves the usage reported at the end of a reply.
+pub type UsageSlot = Arc<Mutex<Option<Usage>>>;
+
+/// Text deltas produced by [`crate::LlmProvider::complete_stream`].
+pub struct TextStream {
+    deltas: Pin<Box<dyn Stream<Item = Result<String>> + Send>>,
+    usage: UsageSlot,
+}
+
+impl TextStream {
+    pub fn new(deltas: impl Stream<Item = Result<String>> + Send + 'static) -> Self {
+        Self { deltas: Box::pin(deltas), usage: UsageSlot::default() }
+    }
+
+    /// Set the usage up front, e.g. when the whole reply is already known.
+    pub fn with_usage(self, usage: Option<Usage>) -> Self {
+        *self.usage.lock().unwrap() = usage;
+        self
+    }
+
+    /// Read usage from `usage`, filled in by whoever produces the deltas.
+    pub fn with_slot(mut self, usage: UsageSlot) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// The slot this stream reports usage through, for a stream wrapping it.
+    pub fn slot(&self) -> UsageSlot {
+        self.usage.clone()
+    }
+
+    /// Usage reported by the provider; known once the stream has ended.
+    pub fn usage(&self) -> Option<Usage> {
+        *self.usage.lock().unwrap()
+    }
+}
+
+impl Stream for TextStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.deltas.as_mut().poll_next(cx)
+    }
+}
+
+/// What a single line of a streamed reply means.
+pub(crate) enum Step {
+    /// A text delta.
+    Delta(String),
+    /// A final delta; nothing after it is read.
+    Last(String),
+    /// Keep-alives, comments and empty deltas.
+    Skip,
+    /// End of the reply.
+    Done,
+}
+
+/// Split a byte stream into lines, tolerating chunk boundaries anywhere.
+pub(crate) fn lines<S, B, E>(bytes: S) -> impl Stream<Item = Result<String>> + Send
+where
+    S: Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]>,
+    E: Into<anyhow::Error>,
+{
+    stream::unfold((Box::pin(bytes), Vec::new(), false), |(mut bytes, mut buf, mut eof)| async move {
+        loop {
+            if let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let rest = buf.split_off(pos + 1);
+                let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+                return Some((Ok(line), (bytes, rest, eof)));
+            }
+            if eof {
+                if buf.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+                return Some((Ok(line), (bytes, Vec::new(), true)));
+            }
+            match bytes.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => return Some((Err(e.into()), (bytes, Vec::new(), true))),
+                None => eof = true,
+            }
+        }
+    })
+}
+
+/// Turn a stream of lines into text deltas using `parse`, stopping at [`Step::Done`].
+pub(crate) fn deltas<S, F>(lines: S, parse: F) -> TextStream
+where
+    S: Stream<Item = Result<String>> + Send + 'static,
+    F: FnMut(&str) -> Result<Step> + Send + 'static,
+{
+    TextStream::new(stream::unfold((Box::pin(lines), parse, false), |(mut lines, mut parse, done)| async move {
+        if done {
+            return None;
+        }
+        loop {
+            let line = match lines.next().await? {
+                Ok(line) => line,
+                Err(e) => return Some((Err(e), (lines, parse, true))),
+            };
+            match parse(&line) {
+                Ok(Step::Delta(text)) => return Some((Ok(text), (lines, parse, false))),
+                Ok(Step::Last(text)) => return Some((Ok(text), (lines, parse, true))),
+                Ok(Step::Skip) => continue,
+                Ok(Step::Done) => return None,
+                Err(e) => return Some((Err(e), (lines, parse, true))),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[tokio::test]
+    async fn lines_across_chunks() {
+        let chunks: Vec<std::result::Result<&[u8], std::io::Error>> =
+            vec![Ok(b"data: a"), Ok(b"b\n\nda"), Ok(b"ta: c\ntail")];
+        let out: Vec<String> = lines(stream::iter(chunks)).map(|l| l.unwrap()).collect().await;
+        assert_eq!(out, vec!["data: ab", "", "data: c", "tail"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn deltas_stop_at_done() {
+        let input = stream::iter(["a", "", "b", "end", "c"].map(|s| Ok(s.to_string())));
+        let out: Vec<String> = deltas(input, |l| {
+            Ok(match l {
+                "" => Step::Skip,
+                "end" => Step::Done,
+                s => Step::Delta(s.to_string()),
+            })
+        })
+        .map(|d| d.unwrap())
+        .collect()
+        .await;
+        assert_eq!(out, vec!["a", "b"]);
+    }
+}
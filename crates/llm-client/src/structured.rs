@@ -0,0 +1,207 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use std::fmt;
+
+use crate::{Intent, LlmAction, LlmProvider, Resp, Role, Usage, build_prompt};
+
+/// What the model decided the input is asking for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Command,
+    Explanation,
+    Model,
+    Plugin,
+}
+
+/// How risky the model considers running the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+/// The object the model is asked to return in structured mode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Reply {
+    kind: ActionKind,
+    #[serde(default)]
+    command: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    rationale: String,
+    risk: Risk,
+    confidence: f32,
+}
+
+/// An [`LlmAction`] together with the model's own assessment of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredAction {
+    pub action: LlmAction,
+    pub rationale: Option<String>,
+    pub risk: Risk,
+    pub confidence: f32,
+    /// Tokens spent on the request, when it came from [`infer_structured`].
+    pub usage: Option<Usage>,
+}
+
+/// The model ignored the schema; `resp` holds what it sent instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unstructured {
+    pub resp: Resp,
+}
+
+impl fmt::Display for Unstructured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "reply does not follow the action schema")
+    }
+}
+
+impl std::error::Error for Unstructured {}
+
+/// JSON schema for structured replies.
+pub fn action_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "kind": { "type": "string", "enum": ["command", "explanation", "model", "plugin"] },
+            "command": { "type": "string" },
+            "text": { "type": "string" },
+            "rationale": { "type": "string" },
+            "risk": { "type": "string", "enum": ["low", "medium", "high"] },
+            "confidence": { "type": "number", "minimum": 0, "maximum": 1 }
+        },
+        "required": ["kind", "command", "text", "rationale", "risk", "confidence"],
+        "additionalProperties": false
+    })
+}
+
+const INSTRUCTIONS: &str = "Reply with a single JSON object: \
+`kind` is command, explanation, model or plugin; \
+`command` is the shell command when kind is command, otherwise empty; \
+`text` is the explanation, model name or plugin name for the other kinds; \
+`rationale` briefly says why; `risk` is low, medium or high for running the command; \
+`confidence` is between 0 and 1.";
+
+/// Validate a structured reply and turn it into a [`StructuredAction`].
+pub fn parse_structured(reply: &str) -> Result<StructuredAction> {
+    let start = reply.find('{').ok_or_else(|| anyhow!("structured reply is not a JSON object"))?;
+    let end = reply.rfind('}').ok_or_else(|| anyhow!("structured reply is not a JSON object"))?;
+    let reply: Reply = serde_json::from_str(&reply[start..=end])?;
+    if !(0.0..=1.0).contains(&reply.confidence) {
+        bail!("structured reply confidence {} is outside 0..1", reply.confidence);
+    }
+    let value = match reply.kind {
+        ActionKind::Command => reply.command.trim(),
+        _ => reply.text.trim(),
+    };
+    if value.is_empty() {
+        bail!("structured reply of kind {:?} is empty", reply.kind);
+    }
+    let value = value.to_string();
+    let action = match reply.kind {
+        ActionKind::Command => LlmAction::Command(value),
+        ActionKind::Explanation => LlmAction::Explanation(value),
+        ActionKind::Model => LlmAction::Model(value),
+        ActionKind::Plugin => LlmAction::Plugin(value),
+    };
+    let rationale = Some(reply.rationale.trim().to_string()).filter(|r| !r.is_empty());
+    Ok(StructuredAction { action, rationale, risk: reply.risk, confidence: reply.confidence, usage: None })
+}
+
+/// Like [`crate::infer`], but asks the provider for a typed JSON reply so the
+/// model can pick a different action than the requested `intent`. Fails with
+/// [`Unstructured`] when the reply does not validate.
+pub async fn infer_structured<P: LlmProvider + ?Sized>(
+    provider: &P,
+    intent: Intent,
+    text: &str,
+    context: Option<&str>,
+    failure: Option<&str>,
+) -> Result<StructuredAction> {
+    let mut prompt = build_prompt(intent, text, context, failure);
+    if let Some(system) = prompt.system.as_mut() {
+        system.push_str("\n\n");
+        system.push_str(INSTRUCTIONS);
+    }
+    // Few-shot answers are bare commands; restate them in the reply shape.
+    for turn in prompt.turns.iter_mut().filter(|t| t.role == Role::Assistant) {
+        turn.content = json!({
+            "kind": "command", "command": turn.content, "text": "",
+            "rationale": "", "risk": "low", "confidence": 1.0
+        })
+        .to_string();
+    }
+    let resp = provider.complete(prompt.with_schema(action_schema())).await?;
+    match parse_structured(&resp.text) {
+        Ok(action) => Ok(StructuredAction { usage: resp.usage, ..action }),
+        Err(_) => Err(Unstructured { resp }.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Prompt;
+    use async_trait::async_trait;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(r#"{"kind":"command","command":"ls -la","text":"","rationale":"lists files","risk":"low","confidence":0.9}"#,
+        LlmAction::Command("ls -la".into()), Some("lists files"), Risk::Low)]
+    #[case(r#"```json
+{"kind":"explanation","command":"","text":"-x extracts","rationale":"","risk":"low","confidence":0.7}
+```"#, LlmAction::Explanation("-x extracts".into()), None, Risk::Low)]
+    #[case(r#"{"kind":"command","command":"rm -rf build","rationale":"clean","risk":"high","confidence":0.5}"#,
+        LlmAction::Command("rm -rf build".into()), Some("clean"), Risk::High)]
+    fn parses(#[case] reply: &str, #[case] action: LlmAction, #[case] rationale: Option<&str>, #[case] risk: Risk) {
+        let parsed = parse_structured(reply).unwrap();
+        assert_eq!(parsed.action, action);
+        assert_eq!(parsed.rationale.as_deref(), rationale);
+        assert_eq!(parsed.risk, risk);
+    }
+
+    #[rstest]
+    #[case("ls -la")]
+    #[case(r#"{"kind":"command","command":"","text":"","rationale":"","risk":"low","confidence":0.9}"#)]
+    #[case(r#"{"kind":"command","command":"ls","risk":"extreme","confidence":0.9}"#)]
+    #[case(r#"{"kind":"command","command":"ls","risk":"low","confidence":3}"#)]
+    #[case(r#"{"kind":"shell","command":"ls","risk":"low","confidence":0.5}"#)]
+    fn rejects(#[case] reply: &str) {
+        assert!(parse_structured(reply).is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn infer_sends_schema() {
+        struct SchemaProvider;
+
+        #[async_trait]
+        impl LlmProvider for SchemaProvider {
+            async fn complete(&self, req: Prompt) -> Result<Resp> {
+                assert_eq!(req.schema, Some(action_schema()));
+                assert!(req.turns.iter().filter(|t| t.role == Role::Assistant).all(|t| t.content.starts_with('{')));
+                if req.user == "plain" {
+                    return Ok(Resp { text: "tar -xf a.tgz".into(), usage: None });
+                }
+                Ok(Resp {
+                    text: r#"{"kind":"explanation","command":"","text":"extracts an archive","rationale":"question","risk":"low","confidence":0.8}"#.into(),
+                    usage: None,
+                })
+            }
+        }
+
+        let action = infer_structured(&SchemaProvider, Intent::Translate, "what does tar -x do", None, None).await.unwrap();
+        assert_eq!(action.action, LlmAction::Explanation("extracts an archive".into()));
+        assert_eq!(action.confidence, 0.8);
+
+        let err = infer_structured(&SchemaProvider, Intent::Translate, "plain", None, None).await.unwrap_err();
+        assert_eq!(err.downcast::<Unstructured>().unwrap().resp.text, "tar -xf a.tgz");
+    }
+}
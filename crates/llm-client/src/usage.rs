@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Prompt;
+
+/// Tokens spent on one request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// Counted locally because the provider did not report usage.
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self { prompt_tokens, completion_tokens, estimated: false }
+    }
+
+    /// Local estimate for a prompt and its reply.
+    pub fn estimate(prompt: &Prompt, reply: &str) -> Self {
+        // Chat formats spend a few tokens framing each message.
+        let prompt_tokens = prompt.messages().iter().map(|m| estimate_tokens(&m.content) + 4).sum();
+        Self { prompt_tokens, completion_tokens: estimate_tokens(reply), estimated: true }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Rough BPE-style token count: about four characters per token of a word,
+/// one token per symbol.
+pub fn estimate_tokens(text: &str) -> u32 {
+    let mut tokens = 0;
+    let mut word: u32 = 0;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word += 1;
+            continue;
+        }
+        tokens += word.div_ceil(4);
+        word = 0;
+        if !c.is_whitespace() {
+            tokens += 1;
+        }
+    }
+    tokens + word.div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", 0)]
+    #[case("ls", 1)]
+    #[case("ls -la /tmp", 5)]
+    #[case("internationalization", 5)]
+    fn estimates(#[case] text: &str, #[case] tokens: u32) {
+        assert_eq!(estimate_tokens(text), tokens);
+    }
+
+    #[rstest]
+    fn estimate_counts_every_message() {
+        let usage = Usage::estimate(&Prompt::new("list files").with_system("be brief"), "ls");
+        assert_eq!(usage, Usage { prompt_tokens: 14, completion_tokens: 1, estimated: true });
+        assert_eq!(usage.total(), 15);
+    }
+}
@@ -0,0 +1,543 @@
+#![deny(clippy::all)]
+
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{Json, Path, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use futures_util::stream::{self, StreamExt};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use llm_client::estimate_tokens;
+
+/// Which family of endpoints a request came in on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Api {
+    Ollama,
+    OpenAi,
+    Foundry,
+}
+
+/// One entry of a rules file: a regex on the user's prompt and what to answer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    /// Regex matched against the last user message.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Reply text; `$1`, `${name}` expand capture groups.
+    pub response: String,
+    /// Force a streamed (or a single) reply regardless of what the client asked for.
+    pub stream: Option<bool>,
+    /// Delay before the reply starts.
+    pub latency_ms: u64,
+    /// Delay between streamed chunks.
+    pub chunk_delay_ms: u64,
+    /// Fail with this HTTP status instead of answering.
+    pub status: Option<u16>,
+    /// Error message sent with `status`.
+    pub error: Option<String>,
+    /// `Retry-After` seconds sent with `status`.
+    pub retry_after: Option<u64>,
+    /// Only apply to the first `times` matching requests.
+    pub times: Option<u32>,
+    /// Only apply to requests on this API.
+    pub api: Option<Api>,
+}
+
+impl Rule {
+    pub fn new(pattern: impl Into<String>, response: impl Into<String>) -> Self {
+        Self { pattern: pattern.into(), response: response.into(), ..Default::default() }
+    }
+
+    /// Fail with `status` and `message` instead of answering.
+    pub fn fail(mut self, status: u16, message: impl Into<String>) -> Self {
+        self.status = Some(status);
+        self.error = Some(message.into());
+        self
+    }
+
+    pub fn times(mut self, times: u32) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    pub fn latency(mut self, ms: u64) -> Self {
+        self.latency_ms = ms;
+        self
+    }
+}
+
+/// A rules file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Names listed by `/api/tags` and `/v1/models`.
+    pub models: Vec<String>,
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Answer every prompt with `echo <prompt>`.
+    pub fn echo() -> Self {
+        Self { models: vec!["mock".into()], rules: vec![Rule::new(r"(?s)^\s*(.*?)\s*$", "echo $1")] }
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+}
+
+struct Compiled {
+    rule: Rule,
+    regex: Regex,
+    hits: AtomicU32,
+}
+
+struct Mock {
+    models: Vec<String>,
+    rules: Vec<Compiled>,
+}
+
+impl Mock {
+    fn new(rules: Rules) -> Result<Self> {
+        let compiled = rules
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern).with_context(|| format!("bad rule regex {:?}", rule.pattern))?;
+                Ok(Compiled { rule, regex, hits: AtomicU32::new(0) })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { models: rules.models, rules: compiled })
+    }
+
+    /// The first rule that applies, with its response expanded.
+    fn answer(&self, api: Api, prompt: &str) -> Option<(&Rule, String)> {
+        self.rules.iter().find_map(|c| {
+            if c.rule.api.is_some_and(|a| a != api) {
+                return None;
+            }
+            let caps = c.regex.captures(prompt)?;
+            if let Some(times) = c.rule.times
+                && c.hits.fetch_add(1, Ordering::SeqCst) >= times
+            {
+                return None;
+            }
+            let mut text = String::new();
+            caps.expand(&c.rule.response, &mut text);
+            Some((&c.rule, text))
+        })
+    }
+}
+
+/// What the handlers need to know about a request.
+struct Request {
+    api: Api,
+    model: String,
+    /// Every message, for the prompt token count.
+    messages: Vec<String>,
+    stream: bool,
+    include_usage: bool,
+    chat: bool,
+}
+
+impl Request {
+    fn user(&self) -> &str {
+        self.messages.last().map_or("", String::as_str)
+    }
+}
+
+fn contents(body: &Value) -> Vec<String> {
+    body["messages"]
+        .as_array()
+        .map(|m| m.iter().map(|m| m["content"].as_str().unwrap_or_default().to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn error(api: Api, status: StatusCode, message: &str, retry_after: Option<u64>) -> Response {
+    let body = match api {
+        Api::Ollama => json!({ "error": message }),
+        Api::OpenAi | Api::Foundry => json!({ "error": { "message": message, "code": status.as_u16().to_string() } }),
+    };
+    let mut resp = (status, Json(body)).into_response();
+    if let Some(secs) = retry_after {
+        resp.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    resp
+}
+
+/// Split a reply into word-sized deltas, keeping the whitespace.
+fn chunks(text: &str) -> Vec<String> {
+    text.split_inclusive(char::is_whitespace).map(String::from).collect()
+}
+
+fn streamed(lines: Vec<String>, delay: Duration, content_type: &'static str) -> Response {
+    let body = stream::iter(lines).then(move |line| async move {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        Ok::<_, Infallible>(line)
+    });
+    ([(header::CONTENT_TYPE, content_type)], Body::from_stream(body)).into_response()
+}
+
+async fn respond(mock: &Mock, req: Request) -> Response {
+    let Some((rule, text)) = mock.answer(req.api, req.user()) else {
+        return error(req.api, StatusCode::NOT_FOUND, &format!("mock-llm: no rule matches {:?}", req.user()), None);
+    };
+    if rule.latency_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(rule.latency_ms)).await;
+    }
+    if let Some(status) = rule.status {
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return error(req.api, status, rule.error.as_deref().unwrap_or("injected error"), rule.retry_after);
+    }
+    let prompt_tokens: u32 = req.messages.iter().map(|m| estimate_tokens(m)).sum();
+    let completion_tokens = estimate_tokens(&text);
+    let stream = rule.stream.unwrap_or(req.stream);
+    let delay = Duration::from_millis(rule.chunk_delay_ms);
+    match req.api {
+        Api::Ollama => {
+            let piece = |text: &str, done: bool| {
+                let mut v = if req.chat {
+                    json!({ "model": req.model, "message": { "role": "assistant", "content": text }, "done": done })
+                } else {
+                    json!({ "model": req.model, "response": text, "done": done })
+                };
+                if done {
+                    v["prompt_eval_count"] = json!(prompt_tokens);
+                    v["eval_count"] = json!(completion_tokens);
+                }
+                v
+            };
+            if !stream {
+                return Json(piece(&text, true)).into_response();
+            }
+            let mut lines: Vec<String> = chunks(&text).iter().map(|c| format!("{}\n", piece(c, false))).collect();
+            lines.push(format!("{}\n", piece("", true)));
+            streamed(lines, delay, "application/x-ndjson")
+        }
+        Api::OpenAi | Api::Foundry => {
+            let usage = json!({
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens": prompt_tokens + completion_tokens
+            });
+            if !stream {
+                return Json(json!({
+                    "object": "chat.completion",
+                    "model": req.model,
+                    "choices": [{ "index": 0, "message": { "role": "assistant", "content": text }, "finish_reason": "stop" }],
+                    "usage": usage
+                }))
+                .into_response();
+            }
+            let event = |v: Value| format!("data: {v}\n\n");
+            let mut lines: Vec<String> = chunks(&text)
+                .iter()
+                .map(|c| event(json!({ "model": req.model, "choices": [{ "index": 0, "delta": { "content": c } }] })))
+                .collect();
+            if req.include_usage {
+                lines.push(event(json!({ "model": req.model, "choices": [], "usage": usage })));
+            }
+            lines.push("data: [DONE]\n\n".into());
+            streamed(lines, delay, "text/event-stream")
+        }
+    }
+}
+
+type Shared = State<Arc<Mock>>;
+
+/// Parse a JSON body whatever its `Content-Type`; curl and some clients omit it.
+fn json_body(body: &Bytes) -> serde_json::Result<Value> {
+    serde_json::from_slice(body)
+}
+
+fn bad_request(e: serde_json::Error) -> Response {
+    (StatusCode::BAD_REQUEST, format!("mock-llm: bad JSON body: {e}")).into_response()
+}
+
+async fn ollama_generate(State(mock): Shared, body: Bytes) -> Response {
+    let body = match json_body(&body) {
+        Ok(body) => body,
+        Err(e) => return bad_request(e),
+    };
+    let mut messages: Vec<String> = body["system"].as_str().map(String::from).into_iter().collect();
+    messages.push(body["prompt"].as_str().unwrap_or_default().to_string());
+    let req = Request {
+        api: Api::Ollama,
+        model: body["model"].as_str().unwrap_or_default().to_string(),
+        messages,
+        // Ollama streams unless told not to.
+        stream: body["stream"].as_bool().unwrap_or(true),
+        include_usage: true,
+        chat: false,
+    };
+    respond(&mock, req).await
+}
+
+async fn ollama_chat(State(mock): Shared, body: Bytes) -> Response {
+    let body = match json_body(&body) {
+        Ok(body) => body,
+        Err(e) => return bad_request(e),
+    };
+    let req = Request {
+        api: Api::Ollama,
+        model: body["model"].as_str().unwrap_or_default().to_string(),
+        messages: contents(&body),
+        stream: body["stream"].as_bool().unwrap_or(true),
+        include_usage: true,
+        chat: true,
+    };
+    respond(&mock, req).await
+}
+
+fn openai_request(api: Api, model: String, body: &Value) -> Request {
+    Request {
+        api,
+        model,
+        messages: contents(body),
+        stream: body["stream"].as_bool().unwrap_or(false),
+        include_usage: body["stream_options"]["include_usage"].as_bool().unwrap_or(false),
+        chat: true,
+    }
+}
+
+async fn openai_chat(State(mock): Shared, body: Bytes) -> Response {
+    let body = match json_body(&body) {
+        Ok(body) => body,
+        Err(e) => return bad_request(e),
+    };
+    let model = body["model"].as_str().unwrap_or_default().to_string();
+    respond(&mock, openai_request(Api::OpenAi, model, &body)).await
+}
+
+async fn foundry_chat(State(mock): Shared, Path(deployment): Path<String>, body: Bytes) -> Response {
+    let body = match json_body(&body) {
+        Ok(body) => body,
+        Err(e) => return bad_request(e),
+    };
+    respond(&mock, openai_request(Api::Foundry, deployment, &body)).await
+}
+
+async fn ollama_tags(State(mock): Shared) -> Json<Value> {
+    let models: Vec<Value> = mock.models.iter().map(|m| json!({ "name": m, "model": m })).collect();
+    Json(json!({ "models": models }))
+}
+
+async fn openai_models(State(mock): Shared) -> Json<Value> {
+    let data: Vec<Value> = mock.models.iter().map(|m| json!({ "id": m, "object": "model" })).collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+/// Routes for the Ollama, OpenAI-compatible and AI Foundry endpoints.
+pub fn router(rules: Rules) -> Result<Router> {
+    let mock = Arc::new(Mock::new(rules)?);
+    Ok(Router::new()
+        .route("/api/generate", post(ollama_generate))
+        .route("/api/chat", post(ollama_chat))
+        .route("/api/tags", get(ollama_tags))
+        .route("/v1/chat/completions", post(openai_chat))
+        .route("/v1/models", get(openai_models))
+        .route("/openai/deployments/{deployment}/chat/completions", post(foundry_chat))
+        .with_state(mock))
+}
+
+/// A running mock server; stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Serve `rules` on a free local port.
+    pub async fn start(rules: Rules) -> Result<Self> {
+        Self::bind("127.0.0.1:0", rules).await
+    }
+
+    pub async fn bind(addr: &str, rules: Rules) -> Result<Self> {
+        let app = router(rules)?;
+        let listener = TcpListener::bind(addr).await.with_context(|| format!("binding {addr}"))?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self { addr, task: Some(task) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL to put in `LlmConfig::base_url`.
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Serve until the process is stopped.
+    pub async fn wait(mut self) -> Result<()> {
+        if let Some(task) = self.task.take() {
+            task.await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+    use llm_client::{
+        FallbackProvider, FoundryError, FoundryProvider, LlmConfig, LlmProvider, OllamaApi, OllamaProvider,
+        OpenAiProvider, Prompt, Provider, RetryPolicy, Usage,
+    };
+    use rstest::rstest;
+
+    fn cfg(provider: Provider, server: &MockServer) -> LlmConfig {
+        LlmConfig { provider, base_url: server.uri(), model: "llama3".into(), ..Default::default() }
+    }
+
+    fn rules() -> Rules {
+        Rules::default()
+            .rule(Rule::new(r"(?i)^list (\w+)", "ls -la $1"))
+            .rule(Rule { api: Some(Api::Foundry), ..Rule::new("busy", "").fail(429, "slow down") })
+            .rule(Rule::new("busy", "uptime"))
+    }
+
+    #[rstest]
+    #[case(OllamaApi::Generate)]
+    #[case(OllamaApi::Chat)]
+    #[tokio::test]
+    async fn ollama(#[case] api: OllamaApi) {
+        let server = MockServer::start(rules()).await.unwrap();
+        let provider = OllamaProvider::new(cfg(Provider::Ollama, &server)).with_api(api);
+        let resp = provider.complete(Prompt::new("list tmp").with_system("be terse")).await.unwrap();
+        assert_eq!(resp.text, "ls -la tmp");
+        assert_eq!(resp.usage, Some(Usage::new(estimate_tokens("be terse") + 2, estimate_tokens("ls -la tmp"))));
+
+        let deltas: Vec<String> =
+            provider.complete_stream(Prompt::new("list home")).await.unwrap().try_collect().await.unwrap();
+        assert_eq!(deltas, vec!["ls ", "-la ", "home", ""]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn openai_streams_with_usage() {
+        let server = MockServer::start(rules()).await.unwrap();
+        let provider = OpenAiProvider::new(cfg(Provider::Custom, &server));
+        let mut deltas = provider.complete_stream(Prompt::new("list src")).await.unwrap();
+        let mut text = String::new();
+        while let Some(delta) = deltas.try_next().await.unwrap() {
+            text.push_str(&delta);
+        }
+        assert_eq!(text, "ls -la src");
+        assert_eq!(deltas.usage(), Some(Usage::new(2, estimate_tokens("ls -la src"))));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn foundry_error_injection() {
+        let server = MockServer::start(rules()).await.unwrap();
+        let provider = FoundryProvider::new(cfg(Provider::AIFoundry, &server));
+        let err = provider.complete(Prompt::new("busy")).await.unwrap_err();
+        let err = err.downcast_ref::<FoundryError>().unwrap();
+        assert_eq!((err.status.as_u16(), err.message.as_str()), (429, "slow down"));
+
+        let resp = OpenAiProvider::new(cfg(Provider::Custom, &server)).complete(Prompt::new("busy")).await.unwrap();
+        assert_eq!(resp.text, "uptime");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let rules = Rules::default()
+            .rule(Rule { retry_after: Some(0), ..Rule::new(".", "").fail(503, "warming up").times(1) })
+            .rule(Rule::new(".", "pwd").latency(10));
+        let server = MockServer::start(rules).await.unwrap();
+        let cfg = cfg(Provider::Ollama, &server);
+        let policy = RetryPolicy { backoff_ms: 1, ..Default::default() };
+        let provider = FallbackProvider::new(vec![(cfg.clone(), Box::new(OllamaProvider::new(cfg)))], policy);
+        assert_eq!(provider.complete(Prompt::new("where am i")).await.unwrap().text, "pwd");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn unmatched_prompt_and_model_lists() {
+        let server = MockServer::start(Rules { models: vec!["llama3".into(), "phi3".into()], ..rules() }).await.unwrap();
+        let err = OllamaProvider::new(cfg(Provider::Ollama, &server)).complete(Prompt::new("dance")).await.unwrap_err();
+        assert!(err.to_string().contains("no rule matches \"dance\""));
+
+        let tags: Value = http_get(&format!("{}/api/tags", server.uri())).await;
+        assert_eq!(tags["models"][1]["name"], "phi3");
+        let models: Value = http_get(&format!("{}/v1/models", server.uri())).await;
+        assert_eq!(models["data"][0]["id"], "llama3");
+    }
+
+    async fn http_get(url: &str) -> Value {
+        let (host, path) = url.trim_start_matches("http://").split_once('/').unwrap();
+        let mut stream = tokio::net::TcpStream::connect(host).await.unwrap();
+        let request = format!("GET /{path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        tokio::io::AsyncWriteExt::write_all(&mut stream, request.as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut raw).await.unwrap();
+        serde_json::from_str(raw.split_once("\r\n\r\n").unwrap().1).unwrap()
+    }
+
+    #[rstest]
+    fn parses_rules_file() {
+        let rules: Rules = toml::from_str(
+            r#"
+            models = ["llama3"]
+
+            [[rule]]
+            match = "(?i)gif of (.+)"
+            response = "https://gifs.example/$1.gif"
+            stream = false
+            latency_ms = 50
+
+            [[rule]]
+            match = "."
+            api = "openai"
+            status = 429
+            error = "rate limited"
+            retry_after = 2
+            times = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.rules.len(), 2);
+        assert_eq!(rules.rules[0].stream, Some(false));
+        assert_eq!(rules.rules[1].api, Some(Api::OpenAi));
+        let mock = Mock::new(rules).unwrap();
+        assert_eq!(mock.answer(Api::Ollama, "a GIF of cats").unwrap().1, "https://gifs.example/cats.gif");
+    }
+}
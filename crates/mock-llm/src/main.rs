@@ -0,0 +1,29 @@
+#![deny(clippy::all)]
+
+use anyhow::Result;
+use clap::Parser;
+use mock_llm::{MockServer, Rules};
+use std::path::PathBuf;
+
+/// Serve canned Ollama, OpenAI-compatible and AI Foundry replies for testing.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Cli {
+    /// Rules file; without one every prompt is answered with `echo <prompt>`
+    #[arg(long)]
+    rules: Option<PathBuf>,
+    #[arg(long, default_value = "127.0.0.1:11434")]
+    addr: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Cli::parse();
+    let rules = match &args.rules {
+        Some(path) => Rules::load(path)?,
+        None => Rules::echo(),
+    };
+    let server = MockServer::bind(&args.addr, rules).await?;
+    println!("mock-llm listening on {}", server.uri());
+    server.wait().await
+}
@@ -1,7 +1,8 @@
 #![deny(clippy::all)]
 
 use anyhow::{Result, anyhow};
-use llm_client::{LlmConfig, LlmProvider, Prompt, provider_from_config};
+use llm_client::{LlmProvider, Prompt};
+use std::sync::Arc;
 use tokio::runtime::Handle;
 use wasmtime::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store};
 
@@ -9,12 +10,12 @@ struct HostState {
     stdin: String,
     selected: String,
     stdout: String,
-    provider: Box<dyn LlmProvider>,
+    provider: Arc<dyn LlmProvider>,
     handle: Handle,
 }
 
 impl HostState {
-    fn memory<'a>(caller: &'a mut Caller<'_, Self>) -> Result<Memory> {
+    fn memory(caller: &mut Caller<'_, Self>) -> Result<Memory> {
         match caller.get_export("memory") {
             Some(Extern::Memory(m)) => Ok(m),
             _ => Err(anyhow!("memory export not found")),
@@ -51,14 +52,14 @@ impl Plugin {
         })
     }
 
+    /// Prepare a run; the plugin's `llm_complete` calls go to `provider`.
     pub fn instantiate(
         &self,
         engine: &Engine,
-        cfg: LlmConfig,
+        provider: Arc<dyn LlmProvider>,
         stdin: String,
         selected: String,
     ) -> Result<PluginInstance> {
-        let provider = provider_from_config(&cfg);
         let handle = Handle::current();
         let state = HostState {
             stdin,
@@ -104,8 +105,8 @@ impl Plugin {
             |mut caller: Caller<'_, HostState>, in_ptr: i32, in_len: i32, out_ptr: i32| {
                 let prompt = HostState::read_str(&mut caller, in_ptr, in_len)?;
                 let handle = caller.data().handle.clone();
-                let fut = caller.data().provider.complete(Prompt { text: prompt });
-                let resp = tokio::task::block_in_place(|| handle.block_on(fut))?;
+                let provider = caller.data().provider.clone();
+                let resp = tokio::task::block_in_place(|| handle.block_on(provider.complete(Prompt::new(prompt))))?;
                 HostState::write_str(&mut caller, out_ptr, &resp.text)
             },
         )?;
@@ -143,31 +144,23 @@ mod tests {
 
     #[rstest]
     #[tokio::test(flavor = "multi_thread")]
-    #[ignore]
     async fn run_gif_search() {
-        use wiremock::matchers::{method, path};
-        use wiremock::{Mock, MockServer, ResponseTemplate};
-
-        let server = MockServer::start().await;
-        Mock::given(method("POST"))
-            .and(path("/"))
-            .respond_with(
-                ResponseTemplate::new(200)
-                    .set_body_raw("{\"choices\":[{\"text\":\"gif-url\"}]}", "application/json"),
-            )
-            .mount(&server)
-            .await;
+        use mock_llm::{MockServer, Rule, Rules};
+
+        let server = MockServer::start(Rules::default().rule(Rule::new("^cats$", "gif-url"))).await.unwrap();
 
         let engine = Engine::default();
-        let plugin = Plugin::load(&engine, "../../plugins/gif_search.wasm").unwrap();
-        let cfg = LlmConfig {
+        // wasmtime compiles the text format too, so the test runs from source.
+        let plugin = Plugin::load(&engine, "../../plugins/gif_search.wat").unwrap();
+        let cfg = llm_client::LlmConfig {
             provider: llm_client::Provider::Ollama,
             base_url: server.uri(),
             api_key: None,
             model: "test".into(),
+            ..Default::default()
         };
         let mut inst = plugin
-            .instantiate(&engine, cfg, "cats".into(), "cats".into())
+            .instantiate(&engine, Arc::from(llm_client::provider_from_config(&cfg)), "cats".into(), "cats".into())
             .unwrap();
         inst.invoke("run").unwrap();
         assert_eq!(inst.output(), "gif-url");
@@ -1,16 +1,17 @@
 #![deny(clippy::all)]
 
 use anyhow::Result;
-use futures_util::compat::Future01CompatExt;
-use futures::Future as Future01;
-use regex::Regex;
 use std::process::Command;
-use tokio::sync::mpsc::unbounded_channel;
-use tokio_pty_process::{AsyncPtyMaster, CommandExt};
-use tokio_stream::wrappers::UnboundedReceiverStream;
-use tokio_stream::Stream;
-use tokio_compat::runtime::Runtime;
-use tokio01 as tokio_old;
+use tokio::sync::mpsc::{Sender, channel};
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub mod pty;
+pub mod session;
+pub mod vt;
+pub use pty::{Pty, PtySize};
+pub use session::{PtySession, PtyWriter, RawMode};
+pub use vt::{Color, MAX_COLUMNS, Span, Style, StyledLine, VtParser, plain_text};
 
 /// A block of terminal output grouped by prompt.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -18,75 +19,159 @@ pub struct Block {
     pub text: String,
 }
 
-/// Strip ANSI escape sequences from a line.
-fn strip_ansi(input: &str) -> String {
-    // simple matcher for CSI codes
-    static RE: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
-        Regex::new(r"\x1b\[[0-9;]*[mKJ]").unwrap()
-    });
-    RE.replace_all(input, "").into_owned()
+/// What [`run`] reports while a command runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    /// A line of output as soon as it is complete, its escape sequences
+    /// interpreted into plain text and styling spans.
+    Line(StyledLine),
+    /// The unfinished last line as it stands after a chunk, such as a progress
+    /// bar redrawn with `\r`. A later `Partial` or its `Line` replaces it.
+    Partial(StyledLine),
+    /// A block has ended: a new prompt started or the command exited.
+    Block(Block),
 }
 
-/// Split raw output into blocks separated by shell prompts (`$ `).
-pub fn parse_blocks(output: &str) -> Vec<Block> {
-    let mut blocks = Vec::new();
-    let mut current = String::new();
-    for line in output.lines() {
-        let clean = strip_ansi(line);
-        if clean.starts_with("$ ") && !current.is_empty() {
-            blocks.push(Block { text: current.trim_end().to_string() });
-            current.clear();
+/// Splits output into lines and blocks as chunks of it arrive.
+#[derive(Debug, Default)]
+pub struct BlockParser {
+    /// A UTF-8 sequence split across chunks, at most three bytes.
+    partial: Vec<u8>,
+    current: String,
+    vt: VtParser,
+    /// The unfinished line last reported as [`OutputEvent::Partial`].
+    shown: StyledLine,
+}
+
+/// Length of an incomplete UTF-8 sequence at the end of `bytes`.
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let needed = match bytes[bytes.len() - back] {
+            0x80..=0xbf => continue,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return if needed > back { back } else { 0 };
+    }
+    0
+}
+
+impl BlockParser {
+    /// Take the next chunk, returning the events for every line it completes
+    /// and, when it changed, the line still in progress.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<OutputEvent> {
+        self.partial.extend_from_slice(chunk);
+        let end = self.partial.len() - incomplete_tail(&self.partial);
+        let raw: Vec<u8> = self.partial.drain(..end).collect();
+        let mut events = Vec::new();
+        for line in self.vt.feed(&String::from_utf8_lossy(&raw)) {
+            self.line(line, &mut events);
+        }
+        let pending = self.vt.current();
+        if pending != self.shown {
+            self.shown = pending.clone();
+            events.push(OutputEvent::Partial(pending));
+        }
+        events
+    }
+
+    /// Flush the unterminated last line and the block in progress.
+    pub fn finish(&mut self) -> Vec<OutputEvent> {
+        let mut events = Vec::new();
+        let raw = std::mem::take(&mut self.partial);
+        let mut lines = self.vt.feed(&String::from_utf8_lossy(&raw));
+        lines.extend(self.vt.finish());
+        for line in lines {
+            self.line(line, &mut events);
+        }
+        self.end_block(&mut events);
+        events
+    }
+
+    fn line(&mut self, line: StyledLine, events: &mut Vec<OutputEvent>) {
+        self.shown = StyledLine::default();
+        if line.text.starts_with("$ ") {
+            self.end_block(events);
         }
-        if !current.is_empty() {
-            current.push('\n');
+        if !self.current.is_empty() {
+            self.current.push('\n');
         }
-        current.push_str(&clean);
+        self.current.push_str(&line.text);
+        events.push(OutputEvent::Line(line));
     }
-    if !current.is_empty() {
-        blocks.push(Block { text: current.trim_end().to_string() });
+
+    fn end_block(&mut self, events: &mut Vec<OutputEvent>) {
+        let text = self.current.trim_end().to_string();
+        self.current.clear();
+        if !text.is_empty() {
+            events.push(OutputEvent::Block(Block { text }));
+        }
     }
-    blocks
 }
 
-/// Run a command asynchronously and stream its output as [`Block`]s.
-use tokio::sync::oneshot;
+/// Split raw output into blocks separated by shell prompts (`$ `).
+pub fn parse_blocks(output: &str) -> Vec<Block> {
+    let mut parser = BlockParser::default();
+    let mut events = parser.feed(output.as_bytes());
+    events.extend(parser.finish());
+    events
+        .into_iter()
+        .filter_map(|e| match e {
+            OutputEvent::Block(block) => Some(block),
+            OutputEvent::Line(_) | OutputEvent::Partial(_) => None,
+        })
+        .collect()
+}
+
+/// How many events may wait unread before the PTY reader pauses.
+pub const EVENT_BUFFER: usize = 64;
 
 pub struct CommandOutput {
-    pub blocks: UnboundedReceiverStream<Block>,
+    pub events: ReceiverStream<OutputEvent>,
     pub exit: oneshot::Receiver<i32>,
 }
 
-pub async fn run(mut command: Command) -> Result<CommandOutput> {
-    let (tx, rx) = unbounded_channel();
-    let (exit_tx, exit_rx) = oneshot::channel();
-
-    tokio::task::spawn_blocking(move || {
-        let mut rt = Runtime::new().expect("compat runtime");
-        let master = AsyncPtyMaster::open().expect("pty");
-        let mut child = command.spawn_pty_async(&master).expect("spawn");
-        let (reader, _writer) = master.split();
+/// Send `events` while anyone listens; afterwards they are dropped, but the
+/// caller keeps draining the PTY so the child is not blocked on a full one.
+async fn forward(tx: &Sender<OutputEvent>, events: Vec<OutputEvent>, listening: &mut bool) {
+    for event in events {
+        *listening = *listening && tx.send(event).await.is_ok();
+    }
+}
 
-        let read_fut = tokio_old::io::read_to_end(reader, Vec::new())
-            .map(|(_, buf)| buf)
-            .compat();
+/// Run a command on a PTY the size of the host terminal; see [`run_with_size`].
+pub async fn run(command: Command) -> Result<CommandOutput> {
+    run_with_size(command, PtySize::host()).await
+}
 
-        let buf = rt.block_on_std(read_fut).expect("read");
-        let status = rt.block_on_std(child.compat()).map(|s| s.code().unwrap_or(1)).unwrap_or(1);
-        let _ = exit_tx.send(status);
+/// Run a command on a PTY and stream its output as it arrives. Reading pauses
+/// while [`EVENT_BUFFER`] events are waiting, so a slow consumer slows the
+/// command down instead of buffering without bound.
+pub async fn run_with_size(command: Command, size: PtySize) -> Result<CommandOutput> {
+    let mut session = PtySession::spawn_with_size(command, size)?;
+    let (tx, rx) = channel(EVENT_BUFFER);
+    let (exit_tx, exit_rx) = oneshot::channel();
 
-        let text = String::from_utf8_lossy(&buf);
-        for block in parse_blocks(&text) {
-            let _ = tx.send(block);
+    tokio::spawn(async move {
+        let mut parser = BlockParser::default();
+        let mut listening = true;
+        let mut buf = [0u8; 4096];
+        loop {
+            match session.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => forward(&tx, parser.feed(&buf[..n]), &mut listening).await,
+            }
         }
+        forward(&tx, parser.finish(), &mut listening).await;
+        let status = session.wait().await.unwrap_or(1);
+        let _ = exit_tx.send(status);
     });
 
-    Ok(CommandOutput {
-        blocks: UnboundedReceiverStream::new(rx),
-        exit: exit_rx,
-    })
+    Ok(CommandOutput { events: ReceiverStream::new(rx), exit: exit_rx })
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,9 +180,173 @@ mod tests {
     #[rstest]
     #[case("$ echo hi\nhi\n", vec!["$ echo hi\nhi"])]
     #[case("\u{1b}[31mred\u{1b}[0m\n$ done\n", vec!["red", "$ done"])]
+    #[case("$ cargo build\r\n\u{1b}]0;cargo\u{7}  1/3\r  2/3\r\u{1b}[K  done\r\n", vec!["$ cargo build\n  done"])]
     fn parse_cases(#[case] input: &str, #[case] expected: Vec<&str>) {
         let blocks = parse_blocks(input);
         let texts: Vec<String> = blocks.into_iter().map(|b| b.text).collect();
         assert_eq!(texts, expected);
     }
+
+    #[rstest]
+    fn feeds_split_chunks() {
+        let mut parser = BlockParser::default();
+        assert_eq!(parser.feed(b"$ ec"), vec![OutputEvent::Partial("$ ec".into())]);
+        assert_eq!(
+            parser.feed(b"ho hi\r\n\x1b[1mhi\x1b[0m\r\n$ do"),
+            vec![
+                OutputEvent::Line("$ echo hi".into()),
+                OutputEvent::Line(StyledLine {
+                    text: "hi".into(),
+                    spans: vec![Span { range: 0..2, style: Style { bold: true, ..Default::default() } }],
+                }),
+                OutputEvent::Partial("$ do".into())
+            ]
+        );
+        assert_eq!(
+            parser.feed(b"ne\n"),
+            vec![
+                OutputEvent::Block(Block { text: "$ echo hi\nhi".into() }),
+                OutputEvent::Line("$ done".into())
+            ]
+        );
+        assert_eq!(parser.feed("caf\u{e9}".as_bytes().split_at(4).0), vec![OutputEvent::Partial("caf".into())]);
+        assert_eq!(parser.feed(&"caf\u{e9}".as_bytes()[4..]), vec![OutputEvent::Partial("caf\u{e9}".into())]);
+        assert_eq!(
+            parser.finish(),
+            vec![
+                OutputEvent::Line("caf\u{e9}".into()),
+                OutputEvent::Block(Block { text: "$ done\ncaf\u{e9}".into() })
+            ]
+        );
+    }
+
+    #[rstest]
+    fn reports_lines_in_progress() {
+        let mut parser = BlockParser::default();
+        assert_eq!(parser.feed(b"  1/3\r"), vec![OutputEvent::Partial("  1/3".into())]);
+        assert_eq!(parser.feed(b"\x1b[32m"), vec![]);
+        assert_eq!(parser.feed(b"  2/3\r"), vec![OutputEvent::Partial(parser.vt.current())]);
+        assert_eq!(parser.feed(b"\x1b[0m\x1b[K"), vec![OutputEvent::Partial("".into())]);
+        assert_eq!(parser.feed(b"  done\r\n"), vec![OutputEvent::Line("  done".into())]);
+
+        // A line that never ends is wrapped, and only a split character waits.
+        let events = parser.feed(&[b'x'; MAX_COLUMNS * 2 + 10]);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], OutputEvent::Partial("x".repeat(10).as_str().into()));
+        assert!(parser.feed("\u{e9}".as_bytes().split_at(1).0).is_empty());
+        assert_eq!(parser.partial.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[tokio::test]
+    async fn streams_before_exit() {
+        use std::time::Duration;
+        use tokio_stream::StreamExt;
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo first; printf 'step 1/2'; sleep 2; printf '\\rstep 2/2\\n'");
+        let CommandOutput { mut events, exit } = run(command).await.unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(1), events.next()).await.expect("line before exit");
+        assert_eq!(first, Some(OutputEvent::Line("first".into())));
+        let step = tokio::time::timeout(Duration::from_secs(1), events.next()).await.expect("progress before exit");
+        assert_eq!(step, Some(OutputEvent::Partial("step 1/2".into())));
+        let rest: Vec<OutputEvent> = events.filter(|e| !matches!(e, OutputEvent::Partial(_))).collect().await;
+        assert_eq!(
+            rest,
+            vec![OutputEvent::Line("step 2/2".into()), OutputEvent::Block(Block { text: "first\nstep 2/2".into() })]
+        );
+        assert_eq!(exit.await.unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[tokio::test]
+    async fn session_forwards_input() {
+        use std::time::Duration;
+
+        let mut session = PtySession::spawn(Command::new("cat")).unwrap();
+        session.writer().write_all(b"ping\n").await.unwrap();
+        let mut seen = String::new();
+        let mut buf = [0u8; 256];
+        // The terminal echoes the line, then cat prints it back.
+        while seen.matches("ping").count() < 2 {
+            let n = tokio::time::timeout(Duration::from_secs(5), session.read(&mut buf)).await.unwrap().unwrap();
+            assert!(n > 0, "cat closed the terminal early: {seen:?}");
+            seen.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        session.writer().write_all(&[4]).await.unwrap();
+        assert_eq!(session.wait().await.unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[tokio::test]
+    async fn sizes_and_resizes() {
+        use tokio_stream::StreamExt;
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("stty size; read line; stty size");
+        let size = PtySize { rows: 33, cols: 101 };
+        let session = PtySession::spawn_with_size(command, size).unwrap();
+        let mut seen = String::new();
+        let mut buf = [0u8; 256];
+        while !seen.contains("33 101") {
+            let n = session.read(&mut buf).await.unwrap();
+            assert!(n > 0, "{seen:?}");
+            seen.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        session.resize(PtySize { rows: 50, cols: 132 }).unwrap();
+        session.writer().write_all(b"\n").await.unwrap();
+        while !seen.contains("50 132") {
+            let n = session.read(&mut buf).await.unwrap();
+            assert!(n > 0, "{seen:?}");
+            seen.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+
+        let mut command = Command::new("stty");
+        command.arg("size");
+        let CommandOutput { events, .. } = run_with_size(command, PtySize { rows: 10, cols: 40 }).await.unwrap();
+        let events: Vec<OutputEvent> = events.filter(|e| !matches!(e, OutputEvent::Partial(_))).collect().await;
+        assert_eq!(events[0], OutputEvent::Line("10 40".into()));
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[tokio::test]
+    async fn kill_ends_the_session() {
+        use std::time::Duration;
+
+        let mut session = PtySession::spawn(Command::new("cat")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), session.kill()).await.unwrap().unwrap();
+        assert_eq!(session.wait().await.unwrap(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[rstest]
+    #[tokio::test]
+    async fn children_inherit_only_their_terminal() {
+        use tokio_stream::StreamExt;
+
+        let _other = PtySession::spawn(Command::new("cat")).unwrap();
+        let mut command = Command::new("ls");
+        command.arg("-1").arg("/proc/self/fd");
+        let CommandOutput { events, .. } = run(command).await.unwrap();
+        let fds: Vec<String> = events
+            .filter_map(|e| match e {
+                OutputEvent::Line(line) => Some(line.text),
+                _ => None,
+            })
+            .collect()
+            .await;
+        // stdin, stdout, stderr and the directory ls is reading.
+        assert_eq!(fds, vec!["0", "1", "2", "3"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn spawn_failure_is_an_error() {
+        let err = run(Command::new("/nonexistent/clappy-test")).await.err().expect("spawn fails");
+        assert!(err.to_string().contains("/nonexistent/clappy-test"), "{err:#}");
+    }
 }
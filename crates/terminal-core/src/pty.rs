@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use nix::fcntl::{FcntlArg, FdFlag, OFlag, fcntl};
+use nix::pty::{Winsize, openpty};
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use tokio::io::unix::AsyncFd;
+use tokio::process::Child;
+
+/// A terminal window size in character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+impl PtySize {
+    /// The size of the terminal on our stdout, or the default when there is none.
+    pub fn host() -> Self {
+        let mut ws = Winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer.
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0;
+        if ok && ws.ws_row > 0 && ws.ws_col > 0 {
+            Self { rows: ws.ws_row, cols: ws.ws_col }
+        } else {
+            Self::default()
+        }
+    }
+
+    fn winsize(self) -> Winsize {
+        Winsize { ws_row: self.rows, ws_col: self.cols, ws_xpixel: 0, ws_ypixel: 0 }
+    }
+}
+
+/// The master side of a pseudo-terminal, read and written without blocking.
+pub struct Pty {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl Pty {
+    /// Allocate a PTY, returning its master and the slave end for a child.
+    fn open(size: PtySize) -> Result<(Self, OwnedFd)> {
+        let pair = openpty(&size.winsize(), None).context("allocating a PTY")?;
+        let raw = pair.master.as_raw_fd();
+        let flags = OFlag::from_bits_truncate(fcntl(raw, FcntlArg::F_GETFL).context("configuring the PTY")?);
+        fcntl(raw, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK)).context("configuring the PTY")?;
+        // Children spawned meanwhile must not inherit either end; ours gets its
+        // own copies of the slave.
+        for fd in [raw, pair.slave.as_raw_fd()] {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).context("configuring the PTY")?;
+        }
+        let fd = AsyncFd::new(pair.master).context("registering the PTY")?;
+        Ok((Self { fd }, pair.slave))
+    }
+
+    /// Read what the child wrote. `Ok(0)` once every slave handle is closed.
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.try_io(|fd| nix::unistd::read(fd.as_raw_fd(), buf).map_err(io::Error::from)) {
+                // Linux reports EIO rather than EOF once the child side is gone.
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Change the window size; the kernel tells the child with `SIGWINCH`.
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        let ws = size.winsize();
+        // SAFETY: TIOCSWINSZ only reads a `winsize` through the pointer.
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), libc::TIOCSWINSZ, &ws) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Write all of `buf` as if typed on the terminal.
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let mut guard = self.fd.writable().await?;
+            if let Ok(written) = guard.try_io(|fd| nix::unistd::write(fd.get_ref(), buf).map_err(io::Error::from)) {
+                buf = &buf[written?..];
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spawn `command` in a new session whose controlling terminal is a fresh PTY
+/// of the given size.
+pub fn spawn(mut command: Command, size: PtySize) -> Result<(Pty, Child)> {
+    let (pty, slave) = Pty::open(size)?;
+    let program = command.get_program().to_string_lossy().into_owned();
+    command
+        .stdin(Stdio::from(slave.try_clone().context("duplicating the PTY")?))
+        .stdout(Stdio::from(slave.try_clone().context("duplicating the PTY")?))
+        .stderr(Stdio::from(slave));
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // Converting consumes the command, closing our copies of the slave side
+    // so reads end when the child exits.
+    let child = tokio::process::Command::from(command)
+        .spawn()
+        .with_context(|| format!("spawning {program:?} on a PTY"))?;
+    Ok((pty, child))
+}
@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::sys::termios::{self, SetArg, Termios};
+use std::io::{self, IsTerminal, Write};
+use std::os::fd::AsFd;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::process::Child;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::pty::{self, Pty, PtySize};
+use crate::{Block, BlockParser, OutputEvent};
+
+/// What the child reads as end of input (Ctrl-D) once the host's stdin closes.
+const EOT: u8 = 0x04;
+
+/// Sends keystrokes and size changes to a [`PtySession`]; cheap to clone.
+#[derive(Clone)]
+pub struct PtyWriter(Arc<Pty>);
+
+impl PtyWriter {
+    pub async fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf).await
+    }
+
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.0.resize(size)
+    }
+}
+
+/// A child process running on its own PTY, which can be read, written and
+/// handed the host terminal.
+pub struct PtySession {
+    pty: Arc<Pty>,
+    child: Child,
+}
+
+impl PtySession {
+    /// Spawn with the size of the host terminal.
+    pub fn spawn(command: Command) -> Result<Self> {
+        Self::spawn_with_size(command, PtySize::host())
+    }
+
+    pub fn spawn_with_size(command: Command, size: PtySize) -> Result<Self> {
+        let (pty, child) = pty::spawn(command, size)?;
+        Ok(Self { pty: Arc::new(pty), child })
+    }
+
+    /// Change the window size, e.g. when the view showing the session resizes.
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.pty.resize(size)
+    }
+
+    pub fn writer(&self) -> PtyWriter {
+        PtyWriter(self.pty.clone())
+    }
+
+    /// Read what the child wrote. `Ok(0)` once it has closed the terminal.
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pty.read(buf).await
+    }
+
+    /// Wait for the child to exit; death by a signal counts as exit code 1.
+    pub async fn wait(&mut self) -> Result<i32> {
+        let status = self.child.wait().await.context("waiting for the child")?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// Kill the child and wait for it to exit, so a replacement can take its place.
+    pub async fn kill(&mut self) -> Result<()> {
+        self.child.kill().await.context("killing the child")
+    }
+
+    /// Give the child the host terminal until it exits: put the terminal in raw
+    /// mode, forward keystrokes and window size changes, and echo output.
+    /// Returns the exit code and the output as blocks.
+    pub async fn attach(mut self) -> Result<(i32, Vec<Block>)> {
+        let raw = RawMode::enable()?;
+        let mut winch = signal(SignalKind::window_change()).context("watching for terminal resizes")?;
+        let resizer = tokio::spawn({
+            let writer = self.writer();
+            async move {
+                while winch.recv().await.is_some() {
+                    let _ = writer.resize(PtySize::host());
+                }
+            }
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let (keys_tx, mut keys) = mpsc::channel::<Vec<u8>>(16);
+        let reader = tokio::task::spawn_blocking({
+            let stop = stop.clone();
+            move || read_stdin(keys_tx, &stop)
+        });
+        let writer = self.writer();
+        let forwarder = tokio::spawn(async move {
+            while let Some(bytes) = keys.recv().await {
+                if writer.write_all(&bytes).await.is_err() {
+                    return;
+                }
+            }
+            let _ = writer.write_all(&[EOT]).await;
+        });
+        let forwarding = Forwarding { stop, reader: Some(reader), tasks: [forwarder, resizer] };
+
+        let mut parser = BlockParser::default();
+        let mut blocks = Vec::new();
+        let mut keep = |events: Vec<OutputEvent>| {
+            blocks.extend(events.into_iter().filter_map(|e| match e {
+                OutputEvent::Block(block) => Some(block),
+                OutputEvent::Line(_) | OutputEvent::Partial(_) => None,
+            }))
+        };
+        let mut stdout = io::stdout();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.pty.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    stdout.write_all(&buf[..n])?;
+                    stdout.flush()?;
+                    keep(parser.feed(&buf[..n]));
+                }
+            }
+        }
+        keep(parser.finish());
+
+        forwarding.finish().await;
+        drop(raw);
+        Ok((self.wait().await?, blocks))
+    }
+}
+
+/// The tasks [`PtySession::attach`] runs beside the child. Dropping it stops
+/// them, so returning early does not leave stdin being read.
+struct Forwarding {
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Forwarding {
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+
+    /// Stop, then wait until stdin is no longer read.
+    async fn finish(mut self) {
+        self.stop();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.await;
+        }
+    }
+}
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Forward the host's stdin until it closes or `stop` is set. Polls rather
+/// than blocking in `read`, so no keystroke meant for the next prompt is taken.
+fn read_stdin(keys: mpsc::Sender<Vec<u8>>, stop: &AtomicBool) {
+    let stdin = io::stdin();
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::Relaxed) {
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::from(50u16)) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(_) => return,
+        }
+        // Straight from the descriptor: std's buffered stdin would hold back
+        // bytes that `poll` no longer reports.
+        match nix::unistd::read(libc::STDIN_FILENO, &mut buf) {
+            Ok(0) => return,
+            Ok(n) => {
+                if keys.blocking_send(buf[..n].to_vec()).is_err() {
+                    return;
+                }
+            }
+            Err(Errno::EINTR | Errno::EAGAIN) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Keeps the host terminal in raw mode, restoring its settings when dropped.
+/// Does nothing when stdin is not a terminal.
+pub struct RawMode(Option<Termios>);
+
+impl RawMode {
+    pub fn enable() -> Result<Self> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Ok(Self(None));
+        }
+        let saved = termios::tcgetattr(stdin.as_fd()).context("reading terminal settings")?;
+        let mut raw = saved.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &raw).context("switching the terminal to raw mode")?;
+        Ok(Self(Some(saved)))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            let _ = termios::tcsetattr(io::stdin().as_fd(), SetArg::TCSADRAIN, saved);
+        }
+    }
+}
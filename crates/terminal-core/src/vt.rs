@@ -0,0 +1,446 @@
+use std::ops::Range;
+
+/// A colour from an SGR sequence: one of the 256 palette entries or true colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Text attributes set by SGR (`CSI … m`) sequences.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+fn color_codes(color: Color, base: u16, bright: u16, extended: u16) -> String {
+    match color {
+        Color::Indexed(n) if n < 8 => (base + n as u16).to_string(),
+        Color::Indexed(n) if n < 16 => (bright + n as u16 - 8).to_string(),
+        Color::Indexed(n) => format!("{extended};5;{n}"),
+        Color::Rgb(r, g, b) => format!("{extended};2;{r};{g};{b}"),
+    }
+}
+
+impl Style {
+    /// The SGR parameters that select this style from the default one.
+    pub fn sgr(&self) -> String {
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.inverse, "7"),
+            (self.strikethrough, "9"),
+        ];
+        let mut codes: Vec<String> = flags.iter().filter(|(on, _)| *on).map(|(_, code)| code.to_string()).collect();
+        codes.extend(self.fg.map(|c| color_codes(c, 30, 90, 38)));
+        codes.extend(self.bg.map(|c| color_codes(c, 40, 100, 48)));
+        codes.join(";")
+    }
+
+    /// Apply the parameters of one SGR sequence.
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(p) = params.next() {
+            match p {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strikethrough = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.fg = Some(Color::Indexed((p - 30) as u8)),
+                38 => self.fg = extended_color(&mut params),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(Color::Indexed((p - 40) as u8)),
+                48 => self.bg = extended_color(&mut params),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(Color::Indexed((p - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(Color::Indexed((p - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The colour after a `38` or `48`: `5;n` or `2;r;g;b`.
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let mut byte = || params.next().map(|v| v.min(255) as u8);
+    match byte()? {
+        5 => Some(Color::Indexed(byte()?)),
+        2 => Some(Color::Rgb(byte()?, byte()?, byte()?)),
+        _ => None,
+    }
+}
+
+/// A styled run of a [`StyledLine`], as a byte range of its text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub style: Style,
+}
+
+/// One line of output: plain text, with its styling kept apart in spans.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StyledLine {
+    pub text: String,
+    /// Runs of non-default style, in order and not overlapping.
+    pub spans: Vec<Span>,
+}
+
+impl From<&str> for StyledLine {
+    fn from(text: &str) -> Self {
+        Self { text: text.to_string(), spans: Vec::new() }
+    }
+}
+
+impl StyledLine {
+    /// The text with its spans turned back into SGR sequences.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut at = 0;
+        for span in &self.spans {
+            out.push_str(&self.text[at..span.range.start]);
+            out.push_str(&format!("\x1b[{}m{}\x1b[0m", span.style.sgr(), &self.text[span.range.clone()]));
+            at = span.range.end;
+        }
+        out.push_str(&self.text[at..]);
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    OscString,
+    DcsEntry,
+    DcsPassthrough,
+    /// SOS, PM and APC strings, which carry nothing we show.
+    IgnoreString,
+}
+
+/// Widest line kept; longer ones wrap onto the next line as on a terminal.
+pub const MAX_COLUMNS: usize = 4096;
+
+/// A VT500-style escape sequence parser that keeps a single line of "screen":
+/// carriage returns, backspaces and line erases overwrite it the way a
+/// terminal would, so progress bars collapse to their final state. Cursor
+/// movement between lines, titles, hyperlinks and device strings are dropped.
+#[derive(Debug, Default)]
+pub struct VtParser {
+    state: State,
+    params: Vec<u16>,
+    param: Option<u16>,
+    private: bool,
+    style: Style,
+    cells: Vec<(char, Style)>,
+    col: usize,
+}
+
+impl VtParser {
+    /// Parse the next piece of output, returning the lines it completes.
+    pub fn feed(&mut self, input: &str) -> Vec<StyledLine> {
+        let mut lines = Vec::new();
+        for c in input.chars() {
+            self.advance(c, &mut lines);
+        }
+        lines
+    }
+
+    /// The line in progress, without ending it.
+    pub fn current(&self) -> StyledLine {
+        styled(&self.cells)
+    }
+
+    /// The unterminated last line, if it holds anything.
+    pub fn finish(&mut self) -> Option<StyledLine> {
+        self.state = State::Ground;
+        let line = self.take_line();
+        (!line.text.is_empty()).then_some(line)
+    }
+
+    fn advance(&mut self, c: char, lines: &mut Vec<StyledLine>) {
+        // Transitions that apply in every state; ST and the other C1 controls
+        // just end whatever was in progress.
+        let anywhere = match c {
+            '\x1b' => Some(State::Escape),
+            '\u{9b}' => Some(State::CsiEntry),
+            '\u{9d}' => Some(State::OscString),
+            '\u{90}' => Some(State::DcsEntry),
+            '\u{98}' | '\u{9e}' | '\u{9f}' => Some(State::IgnoreString),
+            '\x18' | '\x1a' | '\u{80}'..='\u{9f}' => Some(State::Ground),
+            _ => None,
+        };
+        if let Some(state) = anywhere {
+            self.enter(state);
+            return;
+        }
+        let control = c < ' ';
+        match self.state {
+            State::Ground if control => self.execute(c, lines),
+            State::Ground if c != '\x7f' => {
+                if self.col >= MAX_COLUMNS {
+                    lines.push(self.take_line());
+                }
+                self.print(c)
+            }
+            State::Ground => {}
+            State::Escape | State::EscapeIntermediate | State::CsiEntry | State::CsiParam | State::CsiIntermediate
+            | State::CsiIgnore
+                if control =>
+            {
+                self.execute(c, lines)
+            }
+            State::Escape => match c {
+                '[' => self.enter(State::CsiEntry),
+                ']' => self.state = State::OscString,
+                'P' => self.state = State::DcsEntry,
+                'X' | '^' | '_' => self.state = State::IgnoreString,
+                ' '..='/' => self.state = State::EscapeIntermediate,
+                '\x7f' => {}
+                _ => self.esc_dispatch(c),
+            },
+            State::EscapeIntermediate => match c {
+                ' '..='/' | '\x7f' => {}
+                _ => self.esc_dispatch(c),
+            },
+            State::CsiEntry | State::CsiParam => match c {
+                '0'..='9' => {
+                    let digit = c as u16 - '0' as u16;
+                    self.param = Some(self.param.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                    self.state = State::CsiParam;
+                }
+                ';' | ':' => {
+                    self.params.push(self.param.take().unwrap_or(0));
+                    self.state = State::CsiParam;
+                }
+                '<'..='?' if self.state == State::CsiEntry => {
+                    self.private = true;
+                    self.state = State::CsiParam;
+                }
+                '<'..='?' => self.state = State::CsiIgnore,
+                ' '..='/' => self.state = State::CsiIntermediate,
+                '@'..='~' => self.csi_dispatch(c),
+                _ => {}
+            },
+            State::CsiIntermediate => match c {
+                '0'..='?' => self.state = State::CsiIgnore,
+                '@'..='~' => self.csi_dispatch(c),
+                _ => {}
+            },
+            State::CsiIgnore => {
+                if ('@'..='~').contains(&c) {
+                    self.state = State::Ground;
+                }
+            }
+            // xterm also ends OSC strings with BEL.
+            State::OscString if c == '\x07' => self.state = State::Ground,
+            State::OscString => {}
+            State::DcsEntry => {
+                if ('@'..='~').contains(&c) {
+                    self.state = State::DcsPassthrough;
+                }
+            }
+            State::DcsPassthrough | State::IgnoreString => {}
+        }
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.params.clear();
+        self.param = None;
+        self.private = false;
+    }
+
+    fn execute(&mut self, c: char, lines: &mut Vec<StyledLine>) {
+        match c {
+            '\n' | '\x0b' | '\x0c' => lines.push(self.take_line()),
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.saturating_sub(1),
+            '\t' => self.col = (self.col / 8 + 1) * 8,
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.col < self.cells.len() {
+            self.cells[self.col] = (c, self.style);
+        } else {
+            self.cells.resize(self.col, (' ', Style::default()));
+            self.cells.push((c, self.style));
+        }
+        self.col += 1;
+    }
+
+    fn esc_dispatch(&mut self, c: char) {
+        self.state = State::Ground;
+        // RIS: full reset.
+        if c == 'c' {
+            self.style = Style::default();
+        }
+    }
+
+    fn csi_dispatch(&mut self, c: char) {
+        self.state = State::Ground;
+        if self.private {
+            return;
+        }
+        let mut params = std::mem::take(&mut self.params);
+        params.extend(self.param.take());
+        // Most sequences treat a missing or zero count as one.
+        let n = params.first().copied().unwrap_or(0).max(1) as usize;
+        let blank = (' ', Style::default());
+        match c {
+            'm' if params.is_empty() => self.style = Style::default(),
+            'm' => self.style.apply(&params),
+            'K' => match params.first().copied().unwrap_or(0) {
+                0 => self.cells.truncate(self.col),
+                1 => {
+                    let end = (self.col + 1).min(self.cells.len());
+                    self.cells[..end].fill(blank);
+                }
+                _ => self.cells.clear(),
+            },
+            'C' | 'a' => self.col += n,
+            'D' => self.col = self.col.saturating_sub(n),
+            'G' | '`' => self.col = n - 1,
+            'P' => {
+                let end = (self.col + n).min(self.cells.len());
+                if self.col < end {
+                    self.cells.drain(self.col..end);
+                }
+            }
+            'X' => {
+                let end = (self.col + n).min(self.cells.len());
+                if self.col < end {
+                    self.cells[self.col..end].fill(blank);
+                }
+            }
+            '@' if self.col < self.cells.len() => {
+                let n = n.min(MAX_COLUMNS.saturating_sub(self.col));
+                self.cells.splice(self.col..self.col, std::iter::repeat_n(blank, n));
+                self.cells.truncate(MAX_COLUMNS);
+            }
+            _ => {}
+        }
+    }
+
+    fn take_line(&mut self) -> StyledLine {
+        let cells = std::mem::take(&mut self.cells);
+        self.col = 0;
+        styled(&cells)
+    }
+}
+
+/// Turn a line of cells into text and spans, dropping trailing blanks.
+fn styled(cells: &[(char, Style)]) -> StyledLine {
+    let end = cells.iter().rposition(|(c, _)| !c.is_whitespace()).map_or(0, |i| i + 1);
+    let mut line = StyledLine::default();
+    for &(c, style) in &cells[..end] {
+        let start = line.text.len();
+        line.text.push(c);
+        if style == Style::default() {
+            continue;
+        }
+        match line.spans.last_mut() {
+            Some(span) if span.style == style && span.range.end == start => span.range.end = line.text.len(),
+            _ => line.spans.push(Span { range: start..line.text.len(), style }),
+        }
+    }
+    line
+}
+
+/// Plain text of terminal output, one line per line.
+pub fn plain_text(output: &str) -> String {
+    let mut parser = VtParser::default();
+    let mut lines = parser.feed(output);
+    lines.extend(parser.finish());
+    lines.into_iter().map(|l| l.text).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("\x1b[31mred\x1b[0m", "red")]
+    #[case("a\x1b[3Cb", "a   b")]
+    #[case("a\tb", "a       b")]
+    #[case("\x1b]0;user@host: ~\x07$ ls", "$ ls")]
+    #[case("see \x1b]8;;https://example.com\x1b\\the docs\x1b]8;;\x1b\\.", "see the docs.")]
+    #[case("10%\r50%\r100%", "100%")]
+    #[case("downloading 50%\r\x1b[Kdone", "done")]
+    #[case("abc\x08\x08X", "aXc")]
+    #[case("ab\x08 \x08", "a")]
+    #[case("\x1b[?25l\x1b[?2004hhidden\x1b[?25h", "hidden")]
+    #[case("\x1bPq#0;2;0;0;0\x1b\\after", "after")]
+    #[case("\x1b_apc\x1b\\\x1b(Bok", "ok")]
+    #[case("\u{9b}1mbold", "bold")]
+    #[case("\x1b[2J\x1b[Hcleared", "cleared")]
+    #[case("abcdef\x1b[4D\x1b[2P", "abef")]
+    #[case("one\r\ntwo\r\n", "one\ntwo")]
+    #[case("abc\x1b[2D\x1b[2@", "a  bc")]
+    fn plain(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(plain_text(input), expected);
+    }
+
+    #[rstest]
+    fn keeps_styles_as_spans() {
+        let mut parser = VtParser::default();
+        let lines = parser.feed("plain \x1b[1;38;5;208mhot\x1b[0m and \x1b[38;2;1;2;3mrgb\x1b[39m!\n");
+        let hot = Style { bold: true, fg: Some(Color::Indexed(208)), ..Default::default() };
+        let rgb = Style { fg: Some(Color::Rgb(1, 2, 3)), ..Default::default() };
+        assert_eq!(
+            lines,
+            vec![StyledLine {
+                text: "plain hot and rgb!".into(),
+                spans: vec![Span { range: 6..9, style: hot }, Span { range: 14..17, style: rgb }],
+            }]
+        );
+        assert_eq!(lines[0].render(), "plain \x1b[1;38;5;208mhot\x1b[0m and \x1b[38;2;1;2;3mrgb\x1b[0m!");
+    }
+
+    #[rstest]
+    fn sequences_span_feeds() {
+        let mut parser = VtParser::default();
+        assert_eq!(parser.feed("\x1b[9"), vec![]);
+        assert_eq!(parser.feed("1mwarn\x1b[0m"), vec![]);
+        let line = parser.finish().unwrap();
+        assert_eq!(line.text, "warn");
+        assert_eq!(line.spans[0].style.fg, Some(Color::Indexed(9)));
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[rstest]
+    fn insert_stays_within_a_line() {
+        let mut parser = VtParser::default();
+        parser.feed("ab\x1b[D");
+        for _ in 0..100 {
+            parser.feed("\x1b[65535@");
+        }
+        assert_eq!(parser.cells.len(), MAX_COLUMNS);
+        assert_eq!(parser.finish().unwrap().text, "a");
+    }
+}